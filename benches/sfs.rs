@@ -0,0 +1,77 @@
+//! Joint SFS over a large cohort: unpacked, parallel, and bit-packed once up front
+//!
+//! Run with `cargo bench --bench sfs`.
+
+use bsfs_rust::{
+    bsfs_matrix,
+    packed::{packed_bsfs_matrix, PackedSite},
+    parallel::par_bsfs_matrix,
+    sample_map::SampleMap,
+    vcf::MISSING,
+};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+const N_SAMPLES: usize = 500;
+const N_SITES: u32 = 2000;
+
+/// 500 diploids over three populations
+fn sample_map() -> SampleMap {
+    let populations = ["popA", "popB", "popC"];
+    let haplotypes: Vec<Option<&str>> = (0..2 * N_SAMPLES)
+        .map(|hap| Some(populations[hap / 2 % 3]))
+        .collect();
+
+    SampleMap::from_haplotypes(&haplotypes)
+}
+
+fn calls() -> Vec<Vec<Vec<u32>>> {
+    (0..N_SITES)
+        .map(|idx| {
+            (0..2 * N_SAMPLES as u32)
+                .map(|hap| match (idx * 31 + hap * 17) % 23 {
+                    0 => MISSING,
+                    allele if allele < 5 => 1,
+                    _ => 0,
+                })
+                .collect::<Vec<u32>>()
+                .chunks(2)
+                .map(<[u32]>::to_vec)
+                .collect()
+        })
+        .collect()
+}
+
+fn bench_sfs(c: &mut Criterion) {
+    let sample_map = sample_map();
+    let calls = calls();
+    let packed: Vec<PackedSite> = calls
+        .iter()
+        .map(|site| PackedSite::from_calls(site))
+        .collect();
+
+    // Inputs are cloned outside the timed loop
+    c.bench_function("bsfs_matrix", |b| {
+        b.iter_batched(
+            || calls.clone(),
+            |calls| bsfs_matrix(calls, &sample_map).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+    c.bench_function("par_bsfs_matrix", |b| {
+        b.iter_batched(
+            || calls.clone(),
+            |calls| par_bsfs_matrix(calls, &sample_map).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+    c.bench_function("packed_bsfs_matrix", |b| {
+        b.iter_batched(
+            || packed.clone(),
+            |packed| packed_bsfs_matrix(packed, &sample_map).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+criterion_group!(benches, bench_sfs);
+criterion_main!(benches);
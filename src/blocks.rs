@@ -0,0 +1,465 @@
+use crate::{
+    bsfs_indices, error::BsfsError, mask::CallableMask, sample_map::SampleMap, tally::BlockTally,
+    Site,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+/// Unit in which block length is measured
+///
+/// Serialised as `block_unit` (`bp` or `sites`) and `block_length` fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "block_unit", content = "block_length")]
+pub enum BlockLength {
+    /// Fixed span in base pairs; blocks are aligned to multiples of the length
+    #[serde(rename = "bp")]
+    Bases(u64),
+    /// Fixed number of consecutive (callable) sites
+    #[serde(rename = "sites")]
+    Sites(usize),
+}
+
+impl BlockLength {
+    /// Block length of `length` in `unit`, `bp` or `sites`; None for any other unit
+    pub fn from_unit(unit: &str, length: u64) -> Option<Self> {
+        match unit {
+            "bp" => Some(BlockLength::Bases(length)),
+            "sites" => Some(BlockLength::Sites(length as usize)),
+            _ => None,
+        }
+    }
+
+    /// Unit name, `bp` or `sites`
+    pub fn unit(&self) -> &'static str {
+        match self {
+            BlockLength::Bases(_) => "bp",
+            BlockLength::Sites(_) => "sites",
+        }
+    }
+
+    /// Length in units of `unit`
+    pub fn length(&self) -> u64 {
+        match self {
+            BlockLength::Bases(length) => *length,
+            BlockLength::Sites(length) => *length as u64,
+        }
+    }
+
+    /// Error unless the length is at least 1
+    pub fn validate(&self) -> Result<(), BsfsError> {
+        match self {
+            BlockLength::Bases(0) | BlockLength::Sites(0) => Err(BsfsError::InvalidParameter(
+                "block length must be at least 1".to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Run of sites on one chromosome; `start`/`end` are 0-based half-open, as in BED
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub chrom: String,
+    pub start: u64,
+    pub end: u64,
+    pub sites: Vec<Site>,
+}
+
+/// Partition sites into fixed-length blocks; sites must be sorted by position within each chromosome
+///
+/// In `Bases` mode every block between the first and last site of a chromosome is returned,
+/// including empty ones. In `Sites` mode a trailing incomplete block on each chromosome is dropped.
+pub fn make_blocks(
+    sites: impl IntoIterator<Item = Site>,
+    block_length: BlockLength,
+) -> Result<Vec<Block>, BsfsError> {
+    Ok(SiteBlocks::new(sites, block_length)?.collect())
+}
+
+/// Lazy `make_blocks`: yields each block as soon as the stream of sites has moved past it
+///
+/// Only the block under construction is held in memory, so a tally over a whole chromosome can
+/// be accumulated straight from a `VcfReader`.
+#[derive(Debug, Clone)]
+pub struct SiteBlocks<I> {
+    sites: I,
+    block_length: BlockLength,
+    current: Option<Block>,
+    ready: VecDeque<Block>,
+}
+
+impl<I: Iterator<Item = Site>> SiteBlocks<I> {
+    /// Fails if `block_length` is zero
+    pub fn new(
+        sites: impl IntoIterator<IntoIter = I>,
+        block_length: BlockLength,
+    ) -> Result<Self, BsfsError> {
+        block_length.validate()?;
+
+        Ok(SiteBlocks {
+            sites: sites.into_iter(),
+            block_length,
+            current: None,
+            ready: VecDeque::new(),
+        })
+    }
+
+    fn push_by_bases(&mut self, site: Site, length: u64) {
+        let start = (site.pos - 1) / length * length;
+        let next_start = match &mut self.current {
+            Some(block) if block.chrom == site.chrom && block.start == start => {
+                block.sites.push(site);
+                return;
+            }
+            Some(block) if block.chrom == site.chrom => block.end,
+            _ => start,
+        };
+        self.ready.extend(self.current.take());
+
+        // Fill gap since previous block on this chromosome with empty blocks
+        for gap_start in (next_start..start).step_by(length as usize) {
+            self.ready.push_back(Block {
+                chrom: site.chrom.clone(),
+                start: gap_start,
+                end: gap_start + length,
+                sites: vec![],
+            });
+        }
+
+        self.current = Some(Block {
+            chrom: site.chrom.clone(),
+            start,
+            end: start + length,
+            sites: vec![site],
+        });
+    }
+
+    fn push_by_sites(&mut self, site: Site, length: usize) {
+        match &mut self.current {
+            Some(block) if block.chrom == site.chrom => {
+                block.end = site.pos;
+                block.sites.push(site);
+            }
+            _ => {
+                self.current = Some(Block {
+                    chrom: site.chrom.clone(),
+                    start: site.pos - 1,
+                    end: site.pos,
+                    sites: vec![site],
+                })
+            }
+        }
+
+        if self
+            .current
+            .as_ref()
+            .is_some_and(|block| block.sites.len() == length)
+        {
+            self.ready.extend(self.current.take());
+        }
+    }
+}
+
+impl<I: Iterator<Item = Site>> Iterator for SiteBlocks<I> {
+    type Item = Block;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(block) = self.ready.pop_front() {
+                return Some(block);
+            }
+
+            match (self.sites.next(), self.block_length) {
+                (Some(site), BlockLength::Bases(length)) => self.push_by_bases(site, length),
+                (Some(site), BlockLength::Sites(length)) => self.push_by_sites(site, length),
+                (None, BlockLength::Bases(_)) => return self.current.take(),
+                (None, BlockLength::Sites(_)) => return None,
+            }
+        }
+    }
+}
+
+/// Partition callable regions into blocks of `length` callable bases and assign sites to them
+///
+/// Blocks do not cross chromosomes. Blocks whose span from first to last callable base exceeds
+/// `max_span` bp are dropped, as is a trailing incomplete block on each chromosome. Sites outside
+/// the mask are ignored, so blocks without sites are monomorphic rather than missing. Fails if
+/// `length` is zero.
+pub fn make_callable_blocks(
+    sites: Vec<Site>,
+    mask: &CallableMask,
+    length: u64,
+    max_span: u64,
+) -> Result<Vec<Block>, BsfsError> {
+    BlockLength::Bases(length).validate()?;
+
+    let mut sites_by_chrom: HashMap<String, Vec<Site>> = HashMap::new();
+    for site in sites {
+        sites_by_chrom
+            .entry(site.chrom.clone())
+            .or_default()
+            .push(site);
+    }
+
+    let mut blocks: Vec<Block> = vec![];
+
+    for chrom in mask.chroms() {
+        let mut chrom_sites = sites_by_chrom
+            .remove(chrom)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|site| mask.contains(&site.chrom, site.pos))
+            .peekable();
+
+        for (start, end) in callable_spans(mask.regions(chrom), length) {
+            while chrom_sites.next_if(|site| site.pos <= start).is_some() {}
+            let block_sites: Vec<Site> =
+                std::iter::from_fn(|| chrom_sites.next_if(|site| site.pos <= end)).collect();
+
+            if end - start <= max_span {
+                blocks.push(Block {
+                    chrom: chrom.to_string(),
+                    start,
+                    end,
+                    sites: block_sites,
+                });
+            }
+        }
+    }
+
+    Ok(blocks)
+}
+
+/// Spans covering consecutive runs of `length` callable bases; `length` must be at least 1
+fn callable_spans(regions: &[(u64, u64)], length: u64) -> Vec<(u64, u64)> {
+    let mut spans: Vec<(u64, u64)> = vec![];
+    let mut block_start: Option<u64> = None;
+    let mut remaining = length;
+
+    for (start, end) in regions {
+        let mut pos = *start;
+        while pos < *end {
+            let span_start = *block_start.get_or_insert(pos);
+            let taken = remaining.min(end - pos);
+            pos += taken;
+            remaining -= taken;
+
+            if remaining == 0 {
+                spans.push((span_start, pos));
+                block_start = None;
+                remaining = length;
+            }
+        }
+    }
+
+    spans
+}
+
+/// Per-block mutation configuration: number of sites of each mutation type
+///
+/// Mutation types are the joint SFS entries in row-major order, excluding the two monomorphic
+/// entries (no derived alleles, all alleles derived); element `i` counts sites at flat index `i + 1`.
+pub fn block_configuration(entries: &[Vec<usize>], shape: &[usize]) -> Vec<u64> {
+    let n_entries: usize = shape.iter().product();
+    let mut configuration: Vec<u64> = vec![0; n_mutation_types(shape)];
+
+    for entry in entries {
+        let flat_idx = entry
+            .iter()
+            .zip(shape)
+            .fold(0, |flat_idx, (idx, dim)| flat_idx * dim + idx);
+
+        if flat_idx > 0 && flat_idx < n_entries - 1 {
+            configuration[flat_idx - 1] += 1;
+        }
+    }
+
+    configuration
+}
+
+/// Number of mutation types for a joint SFS of the given shape
+pub fn n_mutation_types(shape: &[usize]) -> usize {
+    shape.iter().product::<usize>().saturating_sub(2)
+}
+
+/// SFS entry of every site in `block`; fails on sites without one call per haplotype
+pub fn block_entries(block: Block, sample_map: &SampleMap) -> Result<Vec<Vec<usize>>, BsfsError> {
+    for site in &block.sites {
+        site.check_ploidy(sample_map)?;
+    }
+    let calls: Vec<Vec<Vec<u32>>> = block.sites.into_iter().map(|site| site.calls).collect();
+
+    bsfs_indices(calls, sample_map)
+}
+
+/// Tally blocks per mutation configuration, truncating counts at `kmax` (one per mutation type)
+pub fn tally_blocks(
+    blocks: impl IntoIterator<Item = Block>,
+    sample_map: &SampleMap,
+    kmax: Vec<u64>,
+) -> Result<BlockTally, BsfsError> {
+    sample_map.validate()?;
+    let shape = sample_map.sfs_shape();
+    let mut tally = BlockTally::new(kmax);
+
+    for block in blocks {
+        let entries = block_entries(block, sample_map)?;
+
+        tally.add(&block_configuration(&entries, &shape))?;
+    }
+
+    Ok(tally)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::sample_map;
+    use rstest::{fixture, rstest};
+
+    fn site(chrom: &str, pos: u64, calls: Vec<Vec<u32>>) -> Site {
+        Site {
+            chrom: chrom.to_string(),
+            pos,
+            calls,
+        }
+    }
+
+    #[fixture]
+    fn sites() -> Vec<Site> {
+        vec![
+            site("chr1", 1, vec![vec![0, 1], vec![0, 0]]),
+            site("chr1", 4, vec![vec![0, 0], vec![0, 0]]),
+            site("chr1", 12, vec![vec![1, 1], vec![0, 1]]),
+            site("chr2", 3, vec![vec![0, 0], vec![1, 1]]),
+        ]
+    }
+
+    #[rstest]
+    fn test_make_blocks_bases(sites: Vec<Site>) {
+        let blocks = make_blocks(sites, BlockLength::Bases(5)).unwrap();
+        let spans: Vec<(&str, u64, u64, usize)> = blocks
+            .iter()
+            .map(|b| (b.chrom.as_str(), b.start, b.end, b.sites.len()))
+            .collect();
+        let expected = vec![
+            ("chr1", 0, 5, 2),
+            ("chr1", 5, 10, 0),
+            ("chr1", 10, 15, 1),
+            ("chr2", 0, 5, 1),
+        ];
+
+        assert_eq!(spans, expected)
+    }
+
+    #[rstest]
+    fn test_make_blocks_sites(sites: Vec<Site>) {
+        let blocks = make_blocks(sites, BlockLength::Sites(2)).unwrap();
+
+        assert_eq!(blocks.len(), 1);
+        assert_eq!((blocks[0].start, blocks[0].end), (0, 4));
+    }
+
+    #[rstest]
+    #[case(BlockLength::Bases(0))]
+    #[case(BlockLength::Sites(0))]
+    fn test_make_blocks_zero_length(sites: Vec<Site>, #[case] block_length: BlockLength) {
+        let err = make_blocks(sites, block_length).unwrap_err();
+
+        assert_eq!(err.to_string(), "block length must be at least 1");
+    }
+
+    #[rstest]
+    fn test_site_blocks_streamed(sites: Vec<Site>, sample_map: SampleMap) {
+        let mut streamed = SiteBlocks::new(sites.clone(), BlockLength::Bases(5)).unwrap();
+
+        assert_eq!(streamed.next().map(|block| block.sites.len()), Some(2));
+        assert_eq!(
+            SiteBlocks::new(sites.clone(), BlockLength::Sites(1))
+                .unwrap()
+                .count(),
+            sites.len()
+        );
+        assert_eq!(
+            tally_blocks(
+                SiteBlocks::new(sites.clone(), BlockLength::Bases(5)).unwrap(),
+                &sample_map,
+                vec![1; 7]
+            )
+            .unwrap(),
+            tally_blocks(
+                make_blocks(sites, BlockLength::Bases(5)).unwrap(),
+                &sample_map,
+                vec![1; 7]
+            )
+            .unwrap()
+        );
+    }
+
+    #[rstest]
+    fn test_make_callable_blocks(sites: Vec<Site>) {
+        let mask = CallableMask::from_bed(std::io::Cursor::new(
+            "chr1\t0\t2\nchr1\t3\t5\nchr1\t8\t14\nchr2\t0\t10\n",
+        ))
+        .unwrap();
+        let blocks = make_callable_blocks(sites, &mask, 4, 6).unwrap();
+        let spans: Vec<(&str, u64, u64, usize)> = blocks
+            .iter()
+            .map(|b| (b.chrom.as_str(), b.start, b.end, b.sites.len()))
+            .collect();
+        let expected = vec![
+            ("chr1", 0, 5, 2),
+            ("chr1", 8, 12, 1),
+            ("chr2", 0, 4, 1),
+            ("chr2", 4, 8, 0),
+        ];
+
+        assert_eq!(spans, expected)
+    }
+
+    #[rstest]
+    fn test_make_callable_blocks_max_span(sites: Vec<Site>) {
+        let mask =
+            CallableMask::from_bed(std::io::Cursor::new("chr1\t0\t2\nchr1\t10\t12\n")).unwrap();
+
+        assert!(make_callable_blocks(sites, &mask, 4, 6).unwrap().is_empty())
+    }
+
+    #[rstest]
+    fn test_make_callable_blocks_zero_length(sites: Vec<Site>) {
+        let mask = CallableMask::from_bed(std::io::Cursor::new("chr1\t0\t20\n")).unwrap();
+        let err = make_callable_blocks(sites, &mask, 0, 6).unwrap_err();
+
+        assert_eq!(err.to_string(), "block length must be at least 1");
+    }
+
+    #[rstest]
+    fn test_n_mutation_types() {
+        assert_eq!(n_mutation_types(&[3, 3]), 7);
+        assert_eq!(n_mutation_types(&[5, 5, 5]), 123);
+    }
+
+    #[rstest]
+    fn test_block_configuration() {
+        let entries = vec![vec![1, 0], vec![0, 0], vec![1, 0], vec![2, 2], vec![2, 1]];
+
+        assert_eq!(
+            block_configuration(&entries, &[3, 3]),
+            vec![0, 0, 2, 0, 0, 0, 1]
+        )
+    }
+
+    #[rstest]
+    fn test_tally_blocks(sites: Vec<Site>, sample_map: SampleMap) {
+        let blocks = make_blocks(sites, BlockLength::Bases(5)).unwrap();
+        let tally = tally_blocks(blocks, &sample_map, vec![1; 7]).unwrap();
+        let expected = HashMap::from([
+            (vec![0, 0, 1, 0, 0, 0, 0], 1),
+            (vec![0, 0, 0, 0, 0, 0, 0], 1),
+            (vec![0, 0, 0, 0, 0, 0, 1], 1),
+            (vec![0, 1, 0, 0, 0, 0, 0], 1),
+        ]);
+
+        assert_eq!(tally.counts(), &expected)
+    }
+}
@@ -0,0 +1,275 @@
+use crate::error::BsfsError;
+use ndarray::{ArrayD, Dimension, IxDyn};
+use std::{
+    cmp::Ordering,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+const FORMAT: &str = ".fs";
+
+/// Joint SFS in dadi/moments `.fs` form: data, folding flag, population ids and mask
+#[derive(Debug, Clone, PartialEq)]
+pub struct FsSpectrum {
+    pub data: ArrayD<f64>,
+    pub folded: bool,
+    pub populations: Vec<String>,
+    /// True for masked entries
+    pub mask: ArrayD<bool>,
+}
+
+impl FsSpectrum {
+    /// Wrap unfolded joint SFS (e.g. from `bsfs_matrix`) with dadi's default mask, which masks the
+    /// two monomorphic corners
+    pub fn new(data: ArrayD<f64>, populations: Vec<String>) -> Self {
+        let shape = data.shape().to_vec();
+        let mask = ArrayD::from_shape_fn(IxDyn(&shape), |entry| {
+            let entry = entry.slice();
+            entry.iter().all(|idx| *idx == 0)
+                || entry.iter().zip(&shape).all(|(idx, dim)| *idx == dim - 1)
+        });
+
+        FsSpectrum {
+            data,
+            folded: false,
+            populations,
+            mask,
+        }
+    }
+
+    /// Fold as dadi's `Spectrum.fold` does
+    ///
+    /// Entries with more than half of all haplotypes derived are added to their complement and
+    /// masked; entries with exactly half are averaged with their complement.
+    pub fn fold(&self) -> Self {
+        let shape = self.data.shape().to_vec();
+        let n_haplotypes: usize = shape.iter().map(|dim| dim - 1).sum();
+        let data = ArrayD::from_shape_fn(IxDyn(&shape), |entry| {
+            let entry = entry.slice();
+            let complement = IxDyn(&complement(entry, &shape));
+            let (count, reversed) = (self.data[IxDyn(entry)], self.data[complement]);
+
+            match (2 * entry.iter().sum::<usize>()).cmp(&n_haplotypes) {
+                Ordering::Less => count + reversed,
+                Ordering::Equal => (count + reversed) / 2.0,
+                Ordering::Greater => 0.0,
+            }
+        });
+
+        FsSpectrum {
+            data,
+            folded: true,
+            populations: self.populations.clone(),
+            mask: self.folded_mask(),
+        }
+    }
+
+    /// Mask of the folded spectrum: entries masked here or in their complement, and entries with
+    /// more than half of all haplotypes derived
+    fn folded_mask(&self) -> ArrayD<bool> {
+        let shape = self.data.shape().to_vec();
+        let n_haplotypes: usize = shape.iter().map(|dim| dim - 1).sum();
+
+        ArrayD::from_shape_fn(IxDyn(&shape), |entry| {
+            let entry = entry.slice();
+            self.mask[IxDyn(entry)]
+                || self.mask[IxDyn(&complement(entry, &shape))]
+                || 2 * entry.iter().sum::<usize>() > n_haplotypes
+        })
+    }
+
+    /// Write in `.fs` format: dimensions line, data line, mask line
+    pub fn write<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let mut header: Vec<String> = self.data.shape().iter().map(usize::to_string).collect();
+        header.push(if self.folded { "folded" } else { "unfolded" }.to_string());
+        header.extend(
+            self.populations
+                .iter()
+                .map(|population| format!("\"{}\"", population)),
+        );
+
+        writeln!(out, "{}", header.join(" "))?;
+        writeln!(out, "{}", join(self.data.iter()))?;
+        writeln!(
+            out,
+            "{}",
+            join(self.mask.iter().map(|masked| *masked as u8))
+        )?;
+
+        out.flush()
+    }
+
+    /// Write `.fs` file at `path`
+    pub fn to_path<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write(BufWriter::new(File::create(path)?))
+    }
+
+    /// Parse `.fs` format; `#` comment lines are skipped and the mask line is optional
+    pub fn read<R: BufRead>(reader: R) -> Result<Self, BsfsError> {
+        let mut lines = reader
+            .lines()
+            .enumerate()
+            .map(|(idx, line)| line.map(|line| (idx + 1, line)))
+            .filter(|line| {
+                !matches!(line, Ok((_, line)) if line.trim().is_empty() || line.starts_with('#'))
+            });
+
+        let (line_number, header) = lines
+            .next()
+            .transpose()?
+            .ok_or_else(|| BsfsError::parse(FORMAT, 0, "missing dimensions line"))?;
+        let dims_end = header
+            .find(|c: char| c.is_ascii_alphabetic() || c == '"')
+            .unwrap_or(header.len());
+        let (dims, rest) = header.split_at(dims_end);
+        let shape: Vec<usize> = dims
+            .split_whitespace()
+            .map(|dim| dim.parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| {
+                BsfsError::parse(
+                    FORMAT,
+                    line_number,
+                    format!("invalid dimensions '{}'", dims),
+                )
+            })?;
+        let folded = rest.trim_start().starts_with("folded");
+        let populations: Vec<String> = rest
+            .split('"')
+            .skip(1)
+            .step_by(2)
+            .map(String::from)
+            .collect();
+
+        let (line_number, data_line) = lines
+            .next()
+            .transpose()?
+            .ok_or_else(|| BsfsError::parse(FORMAT, line_number + 1, "missing data line"))?;
+        let data: Vec<f64> = data_line
+            .split_whitespace()
+            .map(|value| match value.parse::<f64>() {
+                Ok(value) if value >= 0.0 && value.is_finite() => Ok(value),
+                _ => Err(BsfsError::parse(
+                    FORMAT,
+                    line_number,
+                    format!("expected non-negative count, found '{}'", value),
+                )),
+            })
+            .collect::<Result<_, _>>()?;
+        let data = ArrayD::from_shape_vec(IxDyn(&shape), data).map_err(|_| {
+            BsfsError::parse(
+                FORMAT,
+                line_number,
+                "number of values does not match dimensions",
+            )
+        })?;
+
+        let mut spectrum = FsSpectrum::new(data, populations);
+        if folded {
+            spectrum.folded = true;
+            spectrum.mask = spectrum.folded_mask();
+        }
+        if let Some((line_number, mask_line)) = lines.next().transpose()? {
+            let mask: Vec<bool> = mask_line
+                .split_whitespace()
+                .map(|value| value != "0")
+                .collect();
+            spectrum.mask = ArrayD::from_shape_vec(IxDyn(&shape), mask).map_err(|_| {
+                BsfsError::parse(
+                    FORMAT,
+                    line_number,
+                    "number of mask values does not match dimensions",
+                )
+            })?;
+        }
+
+        Ok(spectrum)
+    }
+
+    /// Read `.fs` file at `path`
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, BsfsError> {
+        FsSpectrum::read(BufReader::new(File::open(path)?))
+    }
+}
+
+/// Entry with every derived count replaced by the ancestral count
+fn complement(entry: &[usize], shape: &[usize]) -> Vec<usize> {
+    entry
+        .iter()
+        .zip(shape)
+        .map(|(idx, dim)| dim - 1 - idx)
+        .collect()
+}
+
+fn join<T: ToString>(values: impl Iterator<Item = T>) -> String {
+    values
+        .map(|value| value.to_string())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::{fixture, rstest};
+    use std::io::Cursor;
+
+    #[fixture]
+    fn spectrum() -> FsSpectrum {
+        let data =
+            ArrayD::from_shape_vec(IxDyn(&[2, 3]), vec![7.0, 1.0, 0.0, 2.0, 0.0, 3.0]).unwrap();
+
+        FsSpectrum::new(data, vec!["popA".to_string(), "popB".to_string()])
+    }
+
+    #[rstest]
+    fn test_write(spectrum: FsSpectrum) {
+        let mut out: Vec<u8> = vec![];
+        spectrum.write(&mut out).unwrap();
+        let expected = "2 3 unfolded \"popA\" \"popB\"\n7 1 0 2 0 3\n1 0 0 0 0 1\n";
+
+        assert_eq!(String::from_utf8(out).unwrap(), expected)
+    }
+
+    #[rstest]
+    fn test_fold() {
+        let data = ArrayD::from_shape_fn(IxDyn(&[3, 3]), |entry| (3 * entry[0] + entry[1]) as f64);
+        let folded = FsSpectrum::new(data, vec![]).fold();
+        let masked: Vec<u8> = folded.mask.iter().map(|m| *m as u8).collect();
+
+        assert!(folded.folded);
+        // Entries with two of four haplotypes derived are averaged with their complement
+        assert_eq!(
+            folded.data.as_slice().unwrap(),
+            &[8.0, 8.0, 4.0, 8.0, 4.0, 0.0, 4.0, 0.0, 0.0]
+        );
+        assert_eq!(masked, vec![1, 0, 0, 0, 0, 1, 0, 1, 1])
+    }
+
+    #[rstest]
+    fn test_round_trip(spectrum: FsSpectrum) {
+        let mut out: Vec<u8> = vec![];
+        spectrum.write(&mut out).unwrap();
+
+        assert_eq!(FsSpectrum::read(Cursor::new(out)).unwrap(), spectrum)
+    }
+
+    #[rstest]
+    fn test_read_dadi_output() {
+        let text = "# dadi output\n3 folded\n0.000000e+00 5.0 0\n1 0 1\n";
+        let spectrum = FsSpectrum::read(Cursor::new(text)).unwrap();
+
+        assert!(spectrum.folded);
+        assert!(spectrum.populations.is_empty());
+        assert_eq!(spectrum.data.as_slice().unwrap(), &[0.0, 5.0, 0.0]);
+        assert_eq!(spectrum.mask.as_slice().unwrap(), &[true, false, true]);
+    }
+
+    #[rstest]
+    fn test_read_malformed() {
+        let err = FsSpectrum::read(Cursor::new("2 2 unfolded\n1 2 3\n")).unwrap_err();
+
+        assert!(err.to_string().contains("line 2"));
+    }
+}
@@ -0,0 +1,138 @@
+use itertools::Itertools;
+use std::{error::Error, fmt, io};
+
+/// Errors from reading input and computing spectra
+#[derive(Debug)]
+pub enum BsfsError {
+    /// I/O failure while reading or writing
+    Io(io::Error),
+    /// Malformed input; `line` is 1-based, or 0 if the error is not tied to a line
+    Parse {
+        format: &'static str,
+        line: usize,
+        msg: String,
+    },
+    /// Genotype that is not a valid allele index for its record
+    MalformedGenotype {
+        line: usize,
+        chrom: String,
+        pos: u64,
+        sample: String,
+        value: String,
+    },
+    /// Site whose number of calls differs from the number of haplotypes in the sample map
+    RaggedPloidy {
+        site: String,
+        expected: usize,
+        found: usize,
+    },
+    /// Haplotype indices without a population
+    UnmappedHaplotypes(Vec<usize>),
+    /// Populations without any haplotypes in the input
+    EmptyPopulations(Vec<String>),
+    /// Inputs that do not fit together, e.g. a sample map with the wrong number of populations
+    Mismatch(String),
+    /// Parameter outside its valid range, e.g. a zero block length
+    InvalidParameter(String),
+    /// Worker thread pool could not be started
+    ThreadPool(rayon::ThreadPoolBuildError),
+}
+
+impl BsfsError {
+    /// Parse error for `format` at 1-based `line`
+    pub fn parse(format: &'static str, line: usize, msg: impl Into<String>) -> Self {
+        BsfsError::Parse {
+            format,
+            line,
+            msg: msg.into(),
+        }
+    }
+}
+
+impl fmt::Display for BsfsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BsfsError::Io(e) => write!(f, "{}", e),
+            BsfsError::Parse { format, line, msg } if *line > 0 => {
+                write!(f, "{} line {}: {}", format, line, msg)
+            }
+            BsfsError::Parse { format, msg, .. } => write!(f, "{}: {}", format, msg),
+            BsfsError::MalformedGenotype {
+                line,
+                chrom,
+                pos,
+                sample,
+                value,
+            } => write!(
+                f,
+                "VCF line {} ({}:{}): malformed genotype '{}' for sample {}",
+                line, chrom, pos, value, sample
+            ),
+            BsfsError::RaggedPloidy {
+                site,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{}: expected {} haplotype calls, found {}",
+                site, expected, found
+            ),
+            BsfsError::UnmappedHaplotypes(haplotypes) => write!(
+                f,
+                "haplotype indices {} are not assigned to a population",
+                haplotypes.iter().join(", ")
+            ),
+            BsfsError::EmptyPopulations(populations) => write!(
+                f,
+                "populations without samples in the input: {}",
+                populations.join(", ")
+            ),
+            BsfsError::Mismatch(msg) => write!(f, "{}", msg),
+            BsfsError::InvalidParameter(msg) => write!(f, "{}", msg),
+            BsfsError::ThreadPool(e) => write!(f, "cannot start thread pool: {}", e),
+        }
+    }
+}
+
+impl Error for BsfsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BsfsError::Io(e) => Some(e),
+            BsfsError::ThreadPool(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for BsfsError {
+    fn from(e: io::Error) -> Self {
+        BsfsError::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    fn test_display() {
+        assert_eq!(
+            BsfsError::parse("BED", 3, "invalid start").to_string(),
+            "BED line 3: invalid start"
+        );
+        assert_eq!(
+            BsfsError::parse(".npy", 0, "missing magic string").to_string(),
+            ".npy: missing magic string"
+        );
+        assert_eq!(
+            BsfsError::RaggedPloidy {
+                site: "chr1:10".to_string(),
+                expected: 4,
+                found: 3
+            }
+            .to_string(),
+            "chr1:10: expected 4 haplotype calls, found 3"
+        );
+    }
+}
@@ -0,0 +1,352 @@
+use crate::{
+    genotype::Genotype,
+    vcf::{VcfRecord, MISSING},
+};
+
+/// Per-genotype thresholds; calls failing any of them are set to missing
+///
+/// A call whose FORMAT value is absent or `.` fails the corresponding threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct GenotypeFilter {
+    /// Minimum FORMAT/GQ
+    pub min_gq: Option<f64>,
+    /// Minimum FORMAT/DP
+    pub min_dp: Option<u64>,
+    /// Maximum FORMAT/DP
+    pub max_dp: Option<u64>,
+    /// Minimum fraction of FORMAT/AD reads supporting the less supported allele of a heterozygote
+    pub min_allele_balance: Option<f64>,
+}
+
+/// Per-site thresholds; failing sites are dropped
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SiteFilter {
+    /// Minimum QUAL; sites with QUAL `.` fail
+    pub min_qual: Option<f64>,
+    /// Require FILTER to be `PASS`
+    pub pass_only: bool,
+    /// Minimum mean FORMAT/DP over samples with a DP value
+    pub min_mean_depth: Option<f64>,
+    /// Maximum mean FORMAT/DP over samples with a DP value
+    pub max_mean_depth: Option<f64>,
+    /// Maximum fraction of samples with a missing call, after genotype filtering
+    pub max_missing: Option<f64>,
+}
+
+/// Genotype and site filters, applied to records before `site_to_entry`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RecordFilter {
+    pub genotype: GenotypeFilter,
+    pub site: SiteFilter,
+}
+
+/// Filtering run statistics
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FilterStats {
+    /// Sites passing all site filters
+    pub passed: u64,
+    pub failed_qual: u64,
+    pub failed_filter: u64,
+    pub failed_depth: u64,
+    pub failed_missing: u64,
+    /// Calls set to missing by genotype filters, on sites reaching them
+    pub masked_genotypes: u64,
+}
+
+/// Records after filtering
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FilterOutcome {
+    pub records: Vec<VcfRecord>,
+    pub stats: FilterStats,
+}
+
+impl GenotypeFilter {
+    /// Whether the call of sample `sample_idx` passes
+    pub fn passes(&self, record: &VcfRecord, sample_idx: usize) -> bool {
+        let values = &record.sample_format[sample_idx];
+
+        if let Some(min_gq) = self.min_gq {
+            if !values.gq.is_some_and(|gq| gq >= min_gq) {
+                return false;
+            }
+        }
+        if self.min_dp.is_some() || self.max_dp.is_some() {
+            let Some(dp) = values.dp else {
+                return false;
+            };
+            if self.min_dp.is_some_and(|min_dp| dp < min_dp)
+                || self.max_dp.is_some_and(|max_dp| dp > max_dp)
+            {
+                return false;
+            }
+        }
+        if let Some(min_allele_balance) = self.min_allele_balance {
+            if let Some(balance) = heterozygote_balance(record, sample_idx) {
+                return balance.is_some_and(|balance| balance >= min_allele_balance);
+            }
+        }
+
+        true
+    }
+
+    /// Set failing calls to missing; returns the number of calls masked
+    pub fn apply(&self, record: &mut VcfRecord) -> u64 {
+        let mut masked = 0;
+
+        for sample_idx in 0..record.calls.len() {
+            let called = record.calls[sample_idx]
+                .iter()
+                .any(|allele| Genotype::from(*allele).is_called());
+            if called && !self.passes(record, sample_idx) {
+                record.calls[sample_idx].fill(MISSING);
+                masked += 1;
+            }
+        }
+
+        masked
+    }
+}
+
+/// Why a site failed `SiteFilter`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SiteFailure {
+    Qual,
+    Filter,
+    Depth,
+    Missing,
+}
+
+impl SiteFilter {
+    /// First failed filter among QUAL, FILTER and mean depth; None if all pass
+    ///
+    /// The missing fraction is checked separately by `check_missing`, after genotype filtering.
+    pub fn check(&self, record: &VcfRecord) -> Option<SiteFailure> {
+        if let Some(min_qual) = self.min_qual {
+            if !record.qual.is_some_and(|qual| qual >= min_qual) {
+                return Some(SiteFailure::Qual);
+            }
+        }
+        if self.pass_only && !record.is_pass() {
+            return Some(SiteFailure::Filter);
+        }
+        if self.min_mean_depth.is_some() || self.max_mean_depth.is_some() {
+            let depth = mean_depth(record);
+            let within = depth.is_some_and(|depth| {
+                self.min_mean_depth.is_none_or(|min| depth >= min)
+                    && self.max_mean_depth.is_none_or(|max| depth <= max)
+            });
+            if !within {
+                return Some(SiteFailure::Depth);
+            }
+        }
+
+        None
+    }
+
+    /// `SiteFailure::Missing` if too many samples have a missing call
+    pub fn check_missing(&self, record: &VcfRecord) -> Option<SiteFailure> {
+        let max_missing = self.max_missing?;
+        if record.calls.is_empty() {
+            return None;
+        }
+        let missing = record
+            .calls
+            .iter()
+            .filter(|calls| {
+                calls
+                    .iter()
+                    .any(|allele| !Genotype::from(*allele).is_called())
+            })
+            .count();
+
+        (missing as f64 / record.calls.len() as f64 > max_missing).then_some(SiteFailure::Missing)
+    }
+}
+
+impl RecordFilter {
+    /// Filter one record in place; false if the site is to be dropped
+    pub fn apply(&self, record: &mut VcfRecord, stats: &mut FilterStats) -> bool {
+        let failure = self.site.check(record).or_else(|| {
+            stats.masked_genotypes += self.genotype.apply(record);
+            self.site.check_missing(record)
+        });
+
+        match failure {
+            None => stats.passed += 1,
+            Some(SiteFailure::Qual) => stats.failed_qual += 1,
+            Some(SiteFailure::Filter) => stats.failed_filter += 1,
+            Some(SiteFailure::Depth) => stats.failed_depth += 1,
+            Some(SiteFailure::Missing) => stats.failed_missing += 1,
+        }
+
+        failure.is_none()
+    }
+}
+
+/// Apply genotype filters, then site filters, to records
+pub fn filter_records(records: Vec<VcfRecord>, filter: &RecordFilter) -> FilterOutcome {
+    let mut outcome = FilterOutcome::default();
+
+    for mut record in records {
+        if filter.apply(&mut record, &mut outcome.stats) {
+            outcome.records.push(record);
+        }
+    }
+
+    outcome
+}
+
+/// Mean FORMAT/DP over samples with a DP value; None if there are none
+fn mean_depth(record: &VcfRecord) -> Option<f64> {
+    let depths: Vec<f64> = record
+        .sample_format
+        .iter()
+        .filter_map(|values| values.dp.map(|dp| dp as f64))
+        .collect();
+
+    (!depths.is_empty()).then(|| depths.iter().sum::<f64>() / depths.len() as f64)
+}
+
+/// Allele balance of a heterozygous call: fraction of AD reads on its less supported allele
+///
+/// None if the call is not heterozygous; Some(None) if AD is absent or has no reads.
+fn heterozygote_balance(record: &VcfRecord, sample_idx: usize) -> Option<Option<f64>> {
+    let mut alleles: Vec<u32> = record.calls[sample_idx]
+        .iter()
+        .copied()
+        .filter(|allele| Genotype::from(*allele).is_called())
+        .collect();
+    alleles.sort_unstable();
+    alleles.dedup();
+    let [a, b] = alleles[..] else {
+        return None;
+    };
+
+    let balance = record.sample_format[sample_idx]
+        .ad
+        .as_ref()
+        .and_then(|depths| {
+            let (a, b) = (*depths.get(a as usize)?, *depths.get(b as usize)?);
+            (a + b > 0).then(|| a.min(b) as f64 / (a + b) as f64)
+        });
+
+    Some(balance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vcf::VcfReader;
+    use rstest::{fixture, rstest};
+    use std::io::Cursor;
+
+    #[fixture]
+    fn records() -> Vec<VcfRecord> {
+        let vcf_text = [
+            "##fileformat=VCFv4.2",
+            "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tind1\tind2\tind3",
+            "chr1\t10\t.\tA\tT\t50\tPASS\t.\tGT:GQ:DP:AD\t0/1:30:10:5,5\t1/1:10:8:0,8\t0/0:40:20:20,0",
+            "chr1\t20\t.\tA\tT\t50\tPASS\t.\tGT:GQ:DP:AD\t0/1:30:10:9,1\t0/1:30:12:6,6\t./.:.:.:.",
+            "chr1\t30\t.\tA\tT\t5\tPASS\t.\tGT:DP\t0/1:10\t0/0:10\t0/0:10",
+            "chr1\t40\t.\tA\tT\t.\tLowQual\t.\tGT:DP\t0/1:100\t0/0:90\t0/0:80",
+        ]
+        .join("\n");
+
+        VcfReader::new(Cursor::new(vcf_text))
+            .unwrap()
+            .map(|record| record.unwrap())
+            .collect()
+    }
+
+    #[rstest]
+    fn test_genotype_filter(mut records: Vec<VcfRecord>) {
+        let filter = GenotypeFilter {
+            min_gq: Some(20.0),
+            min_allele_balance: Some(0.2),
+            ..Default::default()
+        };
+
+        assert_eq!(filter.apply(&mut records[0]), 1);
+        assert_eq!(records[0].calls[1], vec![MISSING, MISSING]);
+        assert_eq!(filter.apply(&mut records[1]), 1);
+        assert_eq!(
+            records[1].calls,
+            vec![vec![MISSING, MISSING], vec![0, 1], vec![MISSING, MISSING]]
+        );
+    }
+
+    #[rstest]
+    fn test_genotype_filter_depth(records: Vec<VcfRecord>) {
+        let filter = GenotypeFilter {
+            min_dp: Some(9),
+            max_dp: Some(15),
+            ..Default::default()
+        };
+        let passes: Vec<bool> = (0..3).map(|idx| filter.passes(&records[0], idx)).collect();
+
+        assert_eq!(passes, vec![true, false, false]);
+    }
+
+    #[rstest]
+    fn test_site_filter(records: Vec<VcfRecord>) {
+        let filter = SiteFilter {
+            min_qual: Some(20.0),
+            pass_only: true,
+            min_mean_depth: Some(10.0),
+            max_mean_depth: Some(50.0),
+            ..Default::default()
+        };
+        let failures: Vec<Option<SiteFailure>> =
+            records.iter().map(|record| filter.check(record)).collect();
+
+        assert_eq!(
+            failures,
+            vec![None, None, Some(SiteFailure::Qual), Some(SiteFailure::Qual)]
+        );
+        assert_eq!(
+            SiteFilter {
+                pass_only: true,
+                ..Default::default()
+            }
+            .check(&records[3]),
+            Some(SiteFailure::Filter)
+        );
+        assert_eq!(
+            SiteFilter {
+                max_mean_depth: Some(50.0),
+                ..Default::default()
+            }
+            .check(&records[3]),
+            Some(SiteFailure::Depth)
+        );
+    }
+
+    #[rstest]
+    fn test_filter_records(records: Vec<VcfRecord>) {
+        let filter = RecordFilter {
+            genotype: GenotypeFilter {
+                min_allele_balance: Some(0.2),
+                ..Default::default()
+            },
+            site: SiteFilter {
+                min_qual: Some(20.0),
+                max_missing: Some(0.5),
+                ..Default::default()
+            },
+        };
+        let outcome = filter_records(records, &filter);
+
+        assert_eq!(outcome.records.len(), 1);
+        assert_eq!(outcome.records[0].pos, 10);
+        assert_eq!(
+            outcome.stats,
+            FilterStats {
+                passed: 1,
+                failed_qual: 2,
+                failed_missing: 1,
+                masked_genotypes: 1,
+                ..Default::default()
+            }
+        );
+    }
+}
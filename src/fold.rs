@@ -0,0 +1,146 @@
+use crate::{
+    blocks::{block_entries, Block},
+    error::BsfsError,
+    sample_map::SampleMap,
+    tally::BlockTally,
+};
+use ndarray::{ArrayD, Dimension, IxDyn};
+use std::{collections::HashMap, ops::AddAssign};
+
+/// Fold joint SFS entry onto the minor-allele configuration across all populations
+///
+/// An entry is replaced by its complement `shape - 1 - entry` when the complement has fewer
+/// derived alleles in total; on ties the lexicographically smaller of the two is kept.
+pub fn fold_entry(entry: &[usize], shape: &[usize]) -> Vec<usize> {
+    let complement: Vec<usize> = entry
+        .iter()
+        .zip(shape)
+        .map(|(idx, dim)| dim - 1 - idx)
+        .collect();
+    let n_derived: usize = entry.iter().sum();
+    let n_complement: usize = complement.iter().sum();
+
+    if n_complement < n_derived || (n_complement == n_derived && complement[..] < entry[..]) {
+        complement
+    } else {
+        entry.to_vec()
+    }
+}
+
+/// Fold joint SFS array, of counts or of projected expected counts; cells folded onto their
+/// complement are left at zero
+pub fn fold_matrix<A: Copy + Default + AddAssign>(matrix: &ArrayD<A>) -> ArrayD<A> {
+    let shape = matrix.shape().to_vec();
+    let mut folded = ArrayD::from_elem(IxDyn(&shape), A::default());
+
+    for (entry, count) in matrix.indexed_iter() {
+        folded[IxDyn(&fold_entry(entry.slice(), &shape))] += *count;
+    }
+
+    folded
+}
+
+/// Folded mutation types: polymorphic entries that are their own fold, in row-major order
+pub fn folded_mutation_types(shape: &[usize]) -> Vec<Vec<usize>> {
+    ArrayD::<u8>::zeros(IxDyn(shape))
+        .indexed_iter()
+        .map(|(entry, _)| entry.slice().to_vec())
+        .filter(|entry| entry.iter().any(|idx| *idx > 0) && fold_entry(entry, shape) == *entry)
+        .collect()
+}
+
+/// Index of each folded mutation type in `folded_mutation_types(shape)`
+pub fn folded_type_index(shape: &[usize]) -> HashMap<Vec<usize>, usize> {
+    folded_mutation_types(shape)
+        .into_iter()
+        .enumerate()
+        .map(|(idx, entry)| (entry, idx))
+        .collect()
+}
+
+/// Per-block folded mutation configuration: number of sites of each folded mutation type
+///
+/// Element `i` counts sites whose folded entry is `folded_mutation_types(shape)[i]`; `types` is
+/// `folded_type_index(shape)`, built once per tally.
+pub fn folded_configuration(
+    entries: &[Vec<usize>],
+    shape: &[usize],
+    types: &HashMap<Vec<usize>, usize>,
+) -> Vec<u64> {
+    let mut configuration: Vec<u64> = vec![0; types.len()];
+
+    for entry in entries {
+        if let Some(idx) = types.get(&fold_entry(entry, shape)) {
+            configuration[*idx] += 1;
+        }
+    }
+
+    configuration
+}
+
+/// Tally blocks per folded mutation configuration, truncating counts at `kmax`
+pub fn tally_blocks_folded(
+    blocks: impl IntoIterator<Item = Block>,
+    sample_map: &SampleMap,
+    kmax: Vec<u64>,
+) -> Result<BlockTally, BsfsError> {
+    sample_map.validate()?;
+    let shape = sample_map.sfs_shape();
+    let types = folded_type_index(&shape);
+    let mut tally = BlockTally::new(kmax);
+
+    for block in blocks {
+        let entries = block_entries(block, sample_map)?;
+
+        tally.add(&folded_configuration(&entries, &shape, &types))?;
+    }
+
+    Ok(tally)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    fn test_fold_entry() {
+        assert_eq!(fold_entry(&[1, 0], &[3, 3]), vec![1, 0]);
+        assert_eq!(fold_entry(&[2, 2], &[3, 3]), vec![0, 0]);
+        assert_eq!(fold_entry(&[2, 1], &[3, 3]), vec![0, 1]);
+        assert_eq!(fold_entry(&[2, 0], &[3, 3]), vec![0, 2]);
+        assert_eq!(fold_entry(&[0, 2], &[3, 3]), vec![0, 2]);
+        assert_eq!(fold_entry(&[3, 1, 0], &[4, 2, 3]), vec![0, 0, 2]);
+    }
+
+    #[rstest]
+    fn test_fold_matrix() {
+        let mut matrix: ArrayD<u64> = ArrayD::zeros(IxDyn(&[3, 3]));
+        matrix[IxDyn(&[1, 0])] = 2;
+        matrix[IxDyn(&[1, 2])] = 3;
+        matrix[IxDyn(&[2, 2])] = 1;
+        let folded = fold_matrix(&matrix);
+
+        assert_eq!(folded[IxDyn(&[1, 0])], 5);
+        assert_eq!(folded[IxDyn(&[0, 0])], 1);
+        assert_eq!(folded.sum(), 6);
+    }
+
+    #[rstest]
+    fn test_folded_mutation_types() {
+        let expected = vec![vec![0, 1], vec![0, 2], vec![1, 0], vec![1, 1]];
+
+        assert_eq!(folded_mutation_types(&[3, 3]), expected)
+    }
+
+    #[rstest]
+    fn test_folded_configuration() {
+        let entries = vec![vec![1, 0], vec![1, 2], vec![2, 0], vec![2, 2], vec![1, 1]];
+        let types = folded_type_index(&[3, 3]);
+
+        assert_eq!(
+            folded_configuration(&entries, &[3, 3], &types),
+            vec![0, 1, 2, 1]
+        )
+    }
+}
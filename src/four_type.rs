@@ -0,0 +1,127 @@
+use crate::{
+    blocks::{block_entries, Block},
+    error::BsfsError,
+    sample_map::SampleMap,
+    tally::BlockTally,
+};
+
+/// Mutation types for one diploid sampled per population (gIMble style)
+///
+/// Classification does not depend on polarisation: e.g. entries (1, 0) and (1, 2) are both `HetA`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MutationType {
+    /// Heterozygous in population A only
+    HetA = 0,
+    /// Heterozygous in population B only
+    HetB = 1,
+    /// Heterozygous in both populations
+    HetAB = 2,
+    /// Fixed difference between populations
+    Fixed = 3,
+}
+
+/// Classify a two-population SFS entry with two haplotypes per population; None if monomorphic
+pub fn classify_entry(entry: &[usize]) -> Option<MutationType> {
+    match entry {
+        [1, 1] => Some(MutationType::HetAB),
+        [1, _] => Some(MutationType::HetA),
+        [_, 1] => Some(MutationType::HetB),
+        [2, 0] | [0, 2] => Some(MutationType::Fixed),
+        _ => None,
+    }
+}
+
+/// Count sites of each mutation type in block; indexed by `MutationType as usize`
+pub fn four_type_configuration(entries: &[Vec<usize>]) -> Vec<u64> {
+    let mut configuration: Vec<u64> = vec![0; 4];
+
+    for mutation_type in entries.iter().filter_map(|entry| classify_entry(entry)) {
+        configuration[mutation_type as usize] += 1;
+    }
+
+    configuration
+}
+
+/// Tally blocks as (hetA, hetB, hetAB, fixed) 4-tuples, truncating counts at `kmax`
+///
+/// Fails unless there are exactly two populations with one diploid (two haplotypes) each.
+pub fn tally_blocks_four_type(
+    blocks: impl IntoIterator<Item = Block>,
+    sample_map: &SampleMap,
+    kmax: Vec<u64>,
+) -> Result<BlockTally, BsfsError> {
+    sample_map.validate()?;
+    check_four_type(sample_map)?;
+
+    let mut tally = BlockTally::new(kmax);
+
+    for block in blocks {
+        let entries = block_entries(block, sample_map)?;
+
+        tally.add(&four_type_configuration(&entries))?;
+    }
+
+    Ok(tally)
+}
+
+/// Error unless `sample_map` has two populations with one diploid each
+pub(crate) fn check_four_type(sample_map: &SampleMap) -> Result<(), BsfsError> {
+    if sample_map.haps_per_pop() == [2, 2] {
+        return Ok(());
+    }
+
+    Err(BsfsError::Mismatch(format!(
+        "four-type classification requires two populations with one diploid each, found {:?} haplotypes",
+        sample_map.haps_per_pop()
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocks::{make_blocks, BlockLength};
+    use crate::test_utils::sample_map;
+    use crate::Site;
+    use rstest::rstest;
+    use std::collections::HashMap;
+
+    #[rstest]
+    fn test_classify_entry() {
+        assert_eq!(classify_entry(&[1, 0]), Some(MutationType::HetA));
+        assert_eq!(classify_entry(&[1, 2]), Some(MutationType::HetA));
+        assert_eq!(classify_entry(&[2, 1]), Some(MutationType::HetB));
+        assert_eq!(classify_entry(&[1, 1]), Some(MutationType::HetAB));
+        assert_eq!(classify_entry(&[0, 2]), Some(MutationType::Fixed));
+        assert_eq!(classify_entry(&[2, 2]), None);
+        assert_eq!(classify_entry(&[0, 0]), None);
+    }
+
+    #[rstest]
+    fn test_four_type_configuration() {
+        let entries = vec![vec![1, 0], vec![0, 1], vec![1, 1], vec![2, 1], vec![0, 0]];
+
+        assert_eq!(four_type_configuration(&entries), vec![1, 2, 1, 0])
+    }
+
+    #[rstest]
+    fn test_tally_blocks_four_type(sample_map: SampleMap) {
+        let sites: Vec<Site> = [
+            (1, vec![vec![0, 1], vec![0, 0]]),
+            (2, vec![vec![1, 1], vec![0, 0]]),
+            (3, vec![vec![0, 1], vec![1, 0]]),
+            (4, vec![vec![0, 0], vec![1, 1]]),
+        ]
+        .into_iter()
+        .map(|(pos, calls)| Site {
+            chrom: "chr1".to_string(),
+            pos,
+            calls,
+        })
+        .collect();
+        let blocks = make_blocks(sites, BlockLength::Sites(2)).unwrap();
+        let tally = tally_blocks_four_type(blocks, &sample_map, vec![2; 4]).unwrap();
+        let expected = HashMap::from([(vec![1, 0, 0, 1], 1), (vec![0, 0, 1, 1], 1)]);
+
+        assert_eq!(tally.counts(), &expected)
+    }
+}
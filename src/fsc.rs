@@ -0,0 +1,202 @@
+use crate::{error::BsfsError, fold::fold_matrix, stats::marginalize};
+use ndarray::{Array2, ArrayD, Ix2, IxDyn};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+};
+
+/// Set the all-ancestral entry to the callable sites not counted in any other entry
+pub fn with_monomorphic(sfs: &ArrayD<u64>, callable_sites: u64) -> ArrayD<u64> {
+    let mut sfs = sfs.clone();
+    let origin = IxDyn(&vec![0; sfs.ndim()]);
+    let counted: u64 = sfs.sum() - sfs[&origin];
+    sfs[&origin] = callable_sites.saturating_sub(counted);
+
+    sfs
+}
+
+/// Two-population marginal of joint SFS; rows are population `i`, columns population `j`
+///
+/// Fails unless `i` and `j` are distinct axes of `sfs`.
+pub fn pairwise_marginal(sfs: &ArrayD<u64>, i: usize, j: usize) -> Result<Array2<u64>, BsfsError> {
+    Ok(marginalize(sfs, &[i, j])?
+        .into_dimensionality::<Ix2>()
+        .expect("two axes remain"))
+}
+
+/// Write two-population SFS as a fastsimcoal2 `_jointDAFpop{i}_{j}.obs` table
+pub fn write_joint_sfs<W: Write>(
+    mut out: W,
+    matrix: &Array2<u64>,
+    i: usize,
+    j: usize,
+) -> io::Result<()> {
+    writeln!(out, "1 observation")?;
+    let columns: Vec<String> = (0..matrix.ncols())
+        .map(|k| format!("d{}_{}", j, k))
+        .collect();
+    writeln!(out, "\t{}", columns.join("\t"))?;
+
+    for (k, row) in matrix.rows().into_iter().enumerate() {
+        let values: Vec<String> = row.iter().map(u64::to_string).collect();
+        writeln!(out, "d{}_{}\t{}", i, k, values.join("\t"))?;
+    }
+
+    out.flush()
+}
+
+/// Write joint SFS as a fastsimcoal2 `_DSFS.obs` multidimensional SFS
+///
+/// Entries are listed with the index of the first population varying fastest.
+pub fn write_multi_sfs<W: Write>(mut out: W, sfs: &ArrayD<u64>) -> io::Result<()> {
+    let sample_sizes: Vec<String> = sfs
+        .shape()
+        .iter()
+        .map(|dim| (dim - 1).to_string())
+        .collect();
+    writeln!(
+        out,
+        "1 observations. No. of demes and sample sizes are on next line"
+    )?;
+    writeln!(out, "{}\t{}", sfs.ndim(), sample_sizes.join("\t"))?;
+
+    let values: Vec<String> = sfs.t().iter().map(u64::to_string).collect();
+    writeln!(out, "{}", values.join("\t"))?;
+
+    out.flush()
+}
+
+/// Write fastsimcoal2 observation files for an unfolded joint SFS, e.g. from `bsfs_matrix`
+///
+/// Population indices follow `SampleMap::populations`. Writes
+/// `{prefix}_jointDAFpop{i}_{j}.obs` for every pair `i > j` and `{prefix}_DSFS.obs`, or the
+/// `jointMAF`/`MSFS` equivalents folded from the unfolded spectra when `folded` is set. The
+/// monomorphic entry is filled from `callable_sites` when given. Returns the written paths.
+pub fn write_fsc_files(
+    prefix: &str,
+    sfs: &ArrayD<u64>,
+    callable_sites: Option<u64>,
+    folded: bool,
+) -> Result<Vec<PathBuf>, BsfsError> {
+    let sfs = match callable_sites {
+        Some(callable_sites) => with_monomorphic(sfs, callable_sites),
+        None => sfs.clone(),
+    };
+    let (joint_tag, multi_tag) = if folded {
+        ("jointMAF", "MSFS")
+    } else {
+        ("jointDAF", "DSFS")
+    };
+    let mut paths: Vec<PathBuf> = vec![];
+
+    for i in 0..sfs.ndim() {
+        for j in 0..i {
+            let mut matrix = pairwise_marginal(&sfs, i, j)?;
+            if folded {
+                matrix = fold_matrix(&matrix.into_dyn())
+                    .into_dimensionality()
+                    .expect("two axes");
+            }
+
+            let path = PathBuf::from(format!("{}_{}pop{}_{}.obs", prefix, joint_tag, i, j));
+            write_joint_sfs(BufWriter::new(File::create(&path)?), &matrix, i, j)?;
+            paths.push(path);
+        }
+    }
+
+    let multi_sfs = if folded { fold_matrix(&sfs) } else { sfs };
+    let path = PathBuf::from(format!("{}_{}.obs", prefix, multi_tag));
+    write_multi_sfs(BufWriter::new(File::create(&path)?), &multi_sfs)?;
+    paths.push(path);
+
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::{fixture, rstest};
+
+    #[fixture]
+    fn sfs() -> ArrayD<u64> {
+        let mut sfs: ArrayD<u64> = ArrayD::zeros(IxDyn(&[3, 2, 2]));
+        sfs[IxDyn(&[1, 0, 0])] = 4;
+        sfs[IxDyn(&[0, 1, 1])] = 2;
+        sfs[IxDyn(&[2, 1, 0])] = 1;
+
+        sfs
+    }
+
+    #[rstest]
+    fn test_with_monomorphic(sfs: ArrayD<u64>) {
+        assert_eq!(with_monomorphic(&sfs, 100)[IxDyn(&[0, 0, 0])], 93);
+        assert_eq!(with_monomorphic(&sfs, 5)[IxDyn(&[0, 0, 0])], 0);
+    }
+
+    #[rstest]
+    fn test_pairwise_marginal(sfs: ArrayD<u64>) {
+        let marginal = pairwise_marginal(&sfs, 1, 0).unwrap();
+
+        assert_eq!(marginal.shape(), &[2, 3]);
+        assert_eq!(marginal, ndarray::array![[0, 4, 0], [2, 0, 1]]);
+        assert_eq!(pairwise_marginal(&sfs, 0, 1).unwrap(), marginal.t());
+        assert!(matches!(
+            pairwise_marginal(&sfs, 1, 1),
+            Err(BsfsError::InvalidParameter(_))
+        ));
+        assert!(matches!(
+            pairwise_marginal(&sfs, 0, 3),
+            Err(BsfsError::InvalidParameter(_))
+        ));
+    }
+
+    #[rstest]
+    fn test_write_joint_sfs() {
+        let mut out: Vec<u8> = vec![];
+        write_joint_sfs(&mut out, &ndarray::array![[5, 1], [2, 0]], 1, 0).unwrap();
+        let expected = "1 observation\n\td0_0\td0_1\nd1_0\t5\t1\nd1_1\t2\t0\n";
+
+        assert_eq!(String::from_utf8(out).unwrap(), expected)
+    }
+
+    #[rstest]
+    fn test_write_multi_sfs() {
+        let sfs = ndarray::array![[5, 1, 0], [2, 0, 3]].into_dyn();
+        let mut out: Vec<u8> = vec![];
+        write_multi_sfs(&mut out, &sfs).unwrap();
+        let lines: Vec<String> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect();
+
+        assert_eq!(lines[1], "2\t1\t2");
+        assert_eq!(lines[2], "5\t2\t1\t0\t0\t3");
+    }
+
+    #[rstest]
+    fn test_write_fsc_files(sfs: ArrayD<u64>) {
+        let prefix = std::env::temp_dir().join("bsfs_rust_test_fsc");
+        let paths = write_fsc_files(prefix.to_str().unwrap(), &sfs, Some(10), false).unwrap();
+        let names: Vec<String> = paths
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        let joint = std::fs::read_to_string(&paths[0]).unwrap();
+        for path in &paths {
+            std::fs::remove_file(path).unwrap();
+        }
+
+        assert_eq!(
+            names,
+            vec![
+                "bsfs_rust_test_fsc_jointDAFpop1_0.obs",
+                "bsfs_rust_test_fsc_jointDAFpop2_0.obs",
+                "bsfs_rust_test_fsc_jointDAFpop2_1.obs",
+                "bsfs_rust_test_fsc_DSFS.obs",
+            ]
+        );
+        assert!(joint.contains("d1_0\t3\t4\t0\n"));
+    }
+}
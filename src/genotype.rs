@@ -0,0 +1,131 @@
+use crate::{blocks::Block, vcf::MISSING};
+
+/// Allele state of a single haplotype call
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Genotype {
+    /// Reference allele (0)
+    Ref,
+    /// n-th alternate allele (n >= 1)
+    Alt(u32),
+    /// Missing call (`.`)
+    Missing,
+}
+
+impl From<u32> for Genotype {
+    fn from(allele: u32) -> Self {
+        match allele {
+            0 => Genotype::Ref,
+            MISSING => Genotype::Missing,
+            n => Genotype::Alt(n),
+        }
+    }
+}
+
+impl Genotype {
+    /// Whether the call is not missing
+    pub fn is_called(&self) -> bool {
+        !matches!(self, Genotype::Missing)
+    }
+
+    /// Whether the call carries an alternate (derived) allele
+    pub fn is_alt(&self) -> bool {
+        matches!(self, Genotype::Alt(_))
+    }
+}
+
+/// How sites and blocks with missing calls are treated
+///
+/// Block configurations are integer counts at the full sample size, so there is no projection
+/// policy here; use `projection::project_sites` to project the SFS of sites with missing calls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissingPolicy {
+    /// Remove sites with any missing call
+    DropSite,
+    /// Remove blocks containing any missing call
+    DropBlock,
+}
+
+/// Whether any call at the site is missing
+pub fn has_missing(calls: &[Vec<u32>]) -> bool {
+    calls
+        .iter()
+        .flatten()
+        .any(|allele| !Genotype::from(*allele).is_called())
+}
+
+/// Apply missing-data policy to blocks
+pub fn apply_missing_policy(blocks: Vec<Block>, policy: MissingPolicy) -> Vec<Block> {
+    match policy {
+        MissingPolicy::DropSite => blocks
+            .into_iter()
+            .map(|mut block| {
+                block.sites.retain(|site| !has_missing(&site.calls));
+                block
+            })
+            .collect(),
+        MissingPolicy::DropBlock => blocks
+            .into_iter()
+            .filter(|block| !block.sites.iter().any(|site| has_missing(&site.calls)))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Site;
+    use rstest::{fixture, rstest};
+
+    #[fixture]
+    fn blocks() -> Vec<Block> {
+        let site = |pos: u64, calls: Vec<Vec<u32>>| Site {
+            chrom: "chr1".to_string(),
+            pos,
+            calls,
+        };
+
+        vec![
+            Block {
+                chrom: "chr1".to_string(),
+                start: 0,
+                end: 2,
+                sites: vec![site(1, vec![vec![0, 1]]), site(2, vec![vec![1, MISSING]])],
+            },
+            Block {
+                chrom: "chr1".to_string(),
+                start: 2,
+                end: 4,
+                sites: vec![site(3, vec![vec![0, 1]])],
+            },
+        ]
+    }
+
+    #[rstest]
+    fn test_genotype_from() {
+        assert_eq!(Genotype::from(0), Genotype::Ref);
+        assert_eq!(Genotype::from(2), Genotype::Alt(2));
+        assert_eq!(Genotype::from(MISSING), Genotype::Missing);
+    }
+
+    #[rstest]
+    fn test_has_missing() {
+        assert!(has_missing(&[vec![0, 1], vec![MISSING, 0]]));
+        assert!(!has_missing(&[vec![0, 1], vec![1]]));
+    }
+
+    #[rstest]
+    fn test_apply_missing_policy_drop_site(blocks: Vec<Block>) {
+        let blocks = apply_missing_policy(blocks, MissingPolicy::DropSite);
+        let n_sites: Vec<usize> = blocks.iter().map(|block| block.sites.len()).collect();
+
+        assert_eq!(n_sites, vec![1, 1])
+    }
+
+    #[rstest]
+    fn test_apply_missing_policy_drop_block(blocks: Vec<Block>) {
+        let blocks = apply_missing_policy(blocks, MissingPolicy::DropBlock);
+
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].start, 2);
+    }
+}
@@ -1,75 +1,182 @@
-use itertools::Itertools;
-use std::{
-    collections::{HashMap, HashSet},
-    hash::Hash,
-};
-use ndarray;
+pub mod blocks;
+pub mod dadi;
+pub mod error;
+pub mod filter;
+pub mod fold;
+pub mod four_type;
+pub mod fsc;
+pub mod genotype;
+pub mod mask;
+pub mod multiallelic;
+pub mod npy;
+pub mod packed;
+pub mod parallel;
+pub mod polarize;
+pub mod popfile;
+pub mod projection;
+pub mod sample_map;
+pub mod sparse;
+pub mod stats;
+pub mod tally;
+#[cfg(test)]
+mod test_utils;
+pub mod vcf;
+pub mod windows;
+
+use error::BsfsError;
+use genotype::Genotype;
+use ndarray::{ArrayD, IxDyn};
+use sample_map::{SampleMap, UNMAPPED};
+use std::borrow::Borrow;
+
+/// Genotype calls at a single genomic position (1-based, as in VCF)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Site {
+    pub chrom: String,
+    pub pos: u64,
+    pub calls: Vec<Vec<u32>>,
+}
+
+impl Site {
+    /// Error unless the site has one call per haplotype of `sample_map`
+    pub fn check_ploidy(&self, sample_map: &SampleMap) -> Result<(), BsfsError> {
+        check_ploidy(&self.calls, sample_map, || {
+            format!("{}:{}", self.chrom, self.pos)
+        })
+    }
+}
 
 /// Flatten site array, i.e. treat individuals as haploid
 pub fn flatten_site(site: Vec<Vec<u32>>) -> Vec<u32> {
     site.into_iter().flatten().collect()
 }
 
-/// Get SFS entry (i,j) from single site of genotypes (biallelic only)
-pub fn site_to_entry(site: Vec<u32>, sample_map: &HashMap<usize, String>) -> Vec<usize> {
-    let mut populations: Vec<&String> = Vec::from_iter(sample_map.values().sorted());
-    populations.dedup(); // make unique list of populations
-    let mut ntons: Vec<usize> = vec![];
+/// Get SFS entry (i,j) from single site of genotypes; missing calls are not counted
+///
+/// Any alternate allele counts as derived, so multiallelic sites should first be resolved with
+/// `multiallelic::apply_multiallelic_policy`. Unmapped haplotypes are ignored here; the SFS and
+/// tally functions reject sample maps that have any (see `SampleMap::validate`).
+pub fn site_to_entry(site: &[u32], sample_map: &SampleMap) -> Vec<usize> {
+    let mut ntons: Vec<usize> = vec![0; sample_map.n_populations()];
+
+    for (val, pop_idx) in site.iter().zip(sample_map.hap_to_pop()) {
+        if *pop_idx != UNMAPPED && Genotype::from(*val).is_alt() {
+            ntons[*pop_idx as usize] += 1;
+        }
+    }
+
+    ntons
+}
 
-    for population in populations {
-        let nton: &usize = &site
-            .iter()
-            .enumerate()
-            .filter(|(idx, _)| *sample_map.get(idx).unwrap() == *population)
-            .filter(|(_, val)| **val != 0 as u32)
-            .count();
+/// Count called (non-missing) haplotypes per population at a single site, in sorted population order
+pub fn called_per_pop(site: &[u32], sample_map: &SampleMap) -> Vec<usize> {
+    let mut called: Vec<usize> = vec![0; sample_map.n_populations()];
 
-        ntons.push(*nton)
+    for (val, pop_idx) in site.iter().zip(sample_map.hap_to_pop()) {
+        if *pop_idx != UNMAPPED && Genotype::from(*val).is_called() {
+            called[*pop_idx as usize] += 1;
+        }
     }
 
-    ntons
+    called
 }
 
-/// Compute bSFS from calls; return indices of entries in bSFS matrix
+/// Compute bSFS from calls; return indices of entries in bSFS matrix
+///
+/// Fails if `sample_map` has unmapped haplotypes or a site does not have one call per haplotype.
 pub fn bsfs_indices(
-    calls: Vec<Vec<Vec<u32>>>,
-    sample_map: HashMap<usize, String>,
-) -> Vec<Vec<usize>> {
-    let indices = calls
-        .into_iter()
-        .map(|site| flatten_site(site))
-        .map(|site| site_to_entry(site, &sample_map))
-        .collect();
+    calls: impl IntoIterator<Item = Vec<Vec<u32>>>,
+    sample_map: &SampleMap,
+) -> Result<Vec<Vec<usize>>, BsfsError> {
+    sample_map.validate()?;
 
-    indices
+    calls
+        .into_iter()
+        .enumerate()
+        .map(|(idx, site)| {
+            check_ploidy(&site, sample_map, || format!("site {}", idx + 1))?;
+            Ok(site_to_entry(&flatten_site(site), sample_map))
+        })
+        .collect()
 }
 
-/// Count number of haplotypes per population
-pub fn n_haps_per_pop(sample_map: &HashMap<usize, String>) -> HashMap<String, usize> {
-    let mut populations: Vec<&String> = Vec::from_iter(sample_map.values().sorted());
-    populations.dedup(); // make unique list of populations
-
-    let mut counts: HashMap<String, usize> = Default::default();
+/// Get joint SFS array for block; one axis per population, in sorted population order
+///
+/// Calls are consumed one site at a time, so they may be streamed rather than collected. Fails as
+/// `bsfs_indices` does.
+pub fn bsfs_matrix(
+    calls: impl IntoIterator<Item = Vec<Vec<u32>>>,
+    sample_map: &SampleMap,
+) -> Result<ArrayD<u64>, BsfsError> {
+    sample_map.validate()?;
+    let mut bsfs_matrix: ArrayD<u64> = ArrayD::zeros(IxDyn(&sample_map.sfs_shape()));
+
+    for (idx, site) in calls.into_iter().enumerate() {
+        check_ploidy(&site, sample_map, || format!("site {}", idx + 1))?;
+        bsfs_matrix[IxDyn(&site_to_entry(&flatten_site(site), sample_map))] += 1;
+    }
 
-    for individual in sample_map.keys() {
-        let pop: String = if let Some(popname) = sample_map.get(individual) {
-            popname.to_string()
-        } else {
-            continue;
-        };
+    Ok(bsfs_matrix)
+}
 
-        *counts.entry(pop).or_default() += 1;
+/// Joint SFS array over a stream of sites, owned or borrowed; errors name the offending site as "chrom:pos"
+///
+/// Fallible streams such as `VcfReader` can be fed through `itertools::process_results`.
+pub fn sfs_from_sites(
+    sites: impl IntoIterator<Item = impl Borrow<Site>>,
+    sample_map: &SampleMap,
+) -> Result<ArrayD<u64>, BsfsError> {
+    sample_map.validate()?;
+    let mut sfs: ArrayD<u64> = ArrayD::zeros(IxDyn(&sample_map.sfs_shape()));
+
+    for site in sites {
+        let site = site.borrow();
+        site.check_ploidy(sample_map)?;
+        sfs[IxDyn(&site_to_entry(&site.calls.concat(), sample_map))] += 1;
     }
 
-    counts
+    Ok(sfs)
 }
 
-/// Get bSFS matrix for block
-pub fn bsfs_matrix(calls: Vec<Vec<Vec<u32>>>, sample_map: HashMap<usize, String>) -> Vec<Vec<u32>> {
-    // Count number of haplotypes
-    let mut haps_per_pop = n_haps_per_pop(&sample_map)
+/// Error unless `calls` has one call per haplotype of `sample_map`, sample by sample if its
+/// ploidies are recorded; `site` names the site in the error
+pub(crate) fn check_ploidy(
+    calls: &[Vec<u32>],
+    sample_map: &SampleMap,
+    site: impl FnOnce() -> String,
+) -> Result<(), BsfsError> {
+    let found: usize = calls.iter().map(Vec::len).sum();
+    if found != sample_map.n_haplotypes() {
+        return Err(BsfsError::RaggedPloidy {
+            site: site(),
+            expected: sample_map.n_haplotypes(),
+            found,
+        });
+    }
 
-    let bsfs_matrix: ndarray::ArrayBase<> = ndarray::ArrayBase::zeros((&haps_per_pop[0], &haps_per_pop[1]));
+    let Some(ploidies) = sample_map.ploidies() else {
+        return Ok(());
+    };
+    if calls.len() != ploidies.len() {
+        return Err(BsfsError::Mismatch(format!(
+            "{}: expected {} samples, found {}",
+            site(),
+            ploidies.len(),
+            calls.len()
+        )));
+    }
+    match calls
+        .iter()
+        .zip(ploidies)
+        .position(|(call, ploidy)| call.len() != *ploidy)
+    {
+        Some(sample) => Err(BsfsError::RaggedPloidy {
+            site: format!("{} sample {}", site(), sample + 1),
+            expected: ploidies[sample],
+            found: calls[sample].len(),
+        }),
+        None => Ok(()),
+    }
 }
 
 #[cfg(test)]
@@ -101,7 +208,7 @@ mod tests {
     }
 
     #[fixture]
-    fn sample_map() -> HashMap<usize, String> {
+    fn sample_map() -> SampleMap {
         let mut samplemap: HashMap<usize, String> = HashMap::new();
         samplemap.insert(0, "popA".to_string());
         samplemap.insert(1, "popA".to_string());
@@ -112,7 +219,7 @@ mod tests {
         samplemap.insert(6, "popB".to_string());
         samplemap.insert(7, "popB".to_string());
 
-        samplemap
+        SampleMap::new(&samplemap)
     }
 
     #[rstest]
@@ -124,35 +231,152 @@ mod tests {
     }
 
     #[rstest]
-    fn test_site_to_entry(flattened_single_call: Vec<u32>, sample_map: HashMap<usize, String>) {
-        let entry: Vec<usize> = site_to_entry(flattened_single_call, &sample_map);
+    fn test_site_to_entry(flattened_single_call: Vec<u32>, sample_map: SampleMap) {
+        let entry: Vec<usize> = site_to_entry(&flattened_single_call, &sample_map);
         let expected = vec![1, 1];
 
         assert_eq!(entry, expected)
     }
 
     #[rstest]
-    fn test_bsfs_indices(block_calls: Vec<Vec<Vec<u32>>>, sample_map: HashMap<usize, String>) {
-        let bsfs_indices = bsfs_indices(block_calls, sample_map);
+    fn test_site_to_entry_missing(sample_map: SampleMap) {
+        let site: Vec<u32> = vec![0, vcf::MISSING, 1, 1, vcf::MISSING, vcf::MISSING, 0, 1];
+
+        assert_eq!(site_to_entry(&site, &sample_map), vec![2, 1]);
+        assert_eq!(called_per_pop(&site, &sample_map), vec![3, 2]);
+    }
+
+    #[rstest]
+    fn test_bsfs_indices(block_calls: Vec<Vec<Vec<u32>>>, sample_map: SampleMap) {
+        let bsfs_indices = bsfs_indices(block_calls, &sample_map).unwrap();
         let expected = vec![[2, 2], [4, 4], [1, 0]];
 
         assert_eq!(bsfs_indices, expected)
     }
 
     #[rstest]
-    fn test_bsfs_matrix(block_calls: Vec<Vec<Vec<u32>>>, sample_map: HashMap<usize, String>) {
-        let block_bsfs = bsfs_matrix(block_calls, sample_map);
-        let expected = vec![[0, 0, 0, 0], [1, 0, 0, 0], [0, 0, 1, 0], [0, 0, 0, 1]];
+    fn test_bsfs_matrix(block_calls: Vec<Vec<Vec<u32>>>, sample_map: SampleMap) {
+        let block_bsfs = bsfs_matrix(block_calls, &sample_map).unwrap();
+        let mut expected: ArrayD<u64> = ArrayD::zeros(IxDyn(&[5, 5]));
+        expected[IxDyn(&[2, 2])] = 1;
+        expected[IxDyn(&[4, 4])] = 1;
+        expected[IxDyn(&[1, 0])] = 1;
 
         assert_eq!(block_bsfs, expected)
     }
 
     #[rstest]
-    fn test_n_haps_per_pop(sample_map: HashMap<usize, String>) {
-        let expected = HashMap::from([("popA".to_string(), 4), ("popB".to_string(), 4)]);
+    fn test_bsfs_matrix_three_pops(block_calls: Vec<Vec<Vec<u32>>>) {
+        let sample_map = SampleMap::new(&HashMap::from([
+            (0, "popA".to_string()),
+            (1, "popA".to_string()),
+            (2, "popB".to_string()),
+            (3, "popB".to_string()),
+            (4, "popB".to_string()),
+            (5, "popB".to_string()),
+            (6, "popC".to_string()),
+            (7, "popC".to_string()),
+        ]));
+        let block_bsfs = bsfs_matrix(block_calls, &sample_map).unwrap();
+        let mut expected: ArrayD<u64> = ArrayD::zeros(IxDyn(&[3, 5, 3]));
+        expected[IxDyn(&[1, 1, 2])] = 1;
+        expected[IxDyn(&[2, 4, 2])] = 1;
+        expected[IxDyn(&[0, 1, 0])] = 1;
 
-        let counts = n_haps_per_pop(&sample_map);
+        assert_eq!(block_bsfs, expected)
+    }
 
-        assert_eq!(counts, expected)
+    #[rstest]
+    fn test_site_to_entry_unmapped() {
+        let sample_map = SampleMap::new(&HashMap::from([
+            (0, "popA".to_string()),
+            (2, "popB".to_string()),
+        ]));
+
+        assert_eq!(site_to_entry(&[1, 1, 1, 1], &sample_map), vec![1, 1]);
+    }
+
+    #[rstest]
+    fn test_sfs_shape(sample_map: SampleMap) {
+        assert_eq!(sample_map.sfs_shape(), vec![5, 5])
+    }
+
+    #[rstest]
+    fn test_haps_per_pop(sample_map: SampleMap) {
+        assert_eq!(sample_map.populations(), &["popA", "popB"]);
+        assert_eq!(sample_map.haps_per_pop(), &[4, 4])
+    }
+
+    #[rstest]
+    fn test_sfs_from_sites_streamed(sample_map: SampleMap) {
+        let vcf_text = [
+            "##fileformat=VCFv4.2",
+            "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\ta\tb\tc\td",
+            "chr1\t5\t.\tA\tT\t.\t.\t.\tGT\t0|1\t1|0\t0|0\t1|1",
+            "chr1\t9\t.\tA\tT\t.\t.\t.\tGT\t0|0\t0|1\t0|0\t0|0",
+        ]
+        .join("\n");
+        let reader = vcf::VcfReader::new(std::io::Cursor::new(vcf_text)).unwrap();
+        let sfs = itertools::process_results(reader, |records| {
+            sfs_from_sites(records.map(Site::from), &sample_map)
+        })
+        .and_then(|sfs| sfs)
+        .unwrap();
+        let mut expected: ArrayD<u64> = ArrayD::zeros(IxDyn(&[5, 5]));
+        expected[IxDyn(&[2, 2])] = 1;
+        expected[IxDyn(&[1, 0])] = 1;
+
+        assert_eq!(sfs, expected)
+    }
+
+    #[rstest]
+    fn test_sfs_from_sites_ragged_ploidy(sample_map: SampleMap) {
+        let site = Site {
+            chrom: "chr2".to_string(),
+            pos: 7,
+            calls: vec![vec![0, 1], vec![1, 0], vec![0, 0], vec![1]],
+        };
+
+        assert_eq!(
+            sfs_from_sites([site], &sample_map).unwrap_err().to_string(),
+            "chr2:7: expected 8 haplotype calls, found 7"
+        );
+    }
+
+    #[rstest]
+    fn test_sfs_from_sites_sample_ploidy(sample_map: SampleMap) {
+        let sample_map = sample_map.with_ploidies(vec![2, 2, 2, 2]).unwrap();
+        let site = Site {
+            chrom: "chr2".to_string(),
+            pos: 7,
+            calls: vec![vec![0, 1], vec![1], vec![0, 0, 1], vec![1, 1]],
+        };
+
+        assert_eq!(
+            sfs_from_sites([site], &sample_map).unwrap_err().to_string(),
+            "chr2:7 sample 2: expected 2 haplotype calls, found 1"
+        );
+    }
+
+    #[rstest]
+    fn test_bsfs_matrix_unmapped() {
+        let sample_map = SampleMap::from_haplotypes(&[Some("popA"), None]);
+        let err = bsfs_matrix(vec![vec![vec![0, 1]]], &sample_map).unwrap_err();
+
+        assert!(matches!(err, BsfsError::UnmappedHaplotypes(ref haps) if haps == &[1]));
+    }
+
+    #[rstest]
+    fn test_bsfs_indices_ragged_ploidy(sample_map: SampleMap) {
+        let calls = vec![
+            vec![vec![0, 1], vec![1, 0], vec![0, 0], vec![1, 1]],
+            vec![vec![0, 1], vec![1], vec![0, 0], vec![1, 1]],
+        ];
+        let err = bsfs_indices(calls, &sample_map).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "site 2: expected 8 haplotype calls, found 7"
+        );
     }
 }
@@ -0,0 +1,927 @@
+use bsfs_rust::{
+    blocks::{make_blocks, make_callable_blocks, n_mutation_types, Block, BlockLength},
+    dadi::FsSpectrum,
+    error::BsfsError,
+    filter::{filter_records, FilterStats, GenotypeFilter, RecordFilter, SiteFilter},
+    fold::{fold_matrix, folded_mutation_types},
+    fsc::write_fsc_files,
+    genotype::{apply_missing_policy, has_missing, MissingPolicy},
+    mask::{filter_sites, CallableMask},
+    multiallelic::{apply_multiallelic_policy, MultiallelicPolicy, MultiallelicStats},
+    npy::NpzSpectrum,
+    parallel::{
+        par_bsfs_matrix, par_tally_blocks, par_tally_blocks_folded, par_tally_blocks_four_type,
+    },
+    polarize::{polarize_records, AncestralFasta, AncestralSource, PolarizationStats},
+    popfile::{assign_haplotypes, infer_ploidies, parse_popfile},
+    projection::{best_projection, project_sites, site_counts},
+    sample_map::SampleMap,
+    sparse::{SparseTally, TallyMetadata},
+    stats::{summary_stats, Count},
+    tally::BlockTally,
+    vcf::{VcfReader, VcfRecord},
+    windows::{
+        make_windows, window_blocks, window_spectra, window_tallies, write_window_spectra,
+        write_window_tallies, WindowSpec,
+    },
+    Site,
+};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use itertools::Itertools;
+use ndarray::{ArrayD, Dimension, IxDyn};
+use std::{
+    collections::HashSet,
+    error::Error,
+    fmt::Display,
+    fs::File,
+    io::{self, BufReader, BufWriter, Write},
+    path::PathBuf,
+};
+
+/// Site frequency spectra and blockwise SFS from VCF
+#[derive(Parser)]
+#[command(name = "bsfs", version)]
+struct Cli {
+    /// Worker threads; 0 uses one per core
+    #[arg(short, long, global = true, default_value_t = 0)]
+    threads: usize,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Joint SFS over all sites; sites with missing calls are dropped unless projected
+    Sfs {
+        #[command(flatten)]
+        input: InputArgs,
+        /// Fold the joint spectrum (ancestral state unknown)
+        #[arg(long)]
+        folded: bool,
+        /// Handling of sites with missing calls; project writes --format tsv or dadi
+        #[arg(long, value_enum, default_value_t = SiteMissingArg::DropSite)]
+        missing: SiteMissingArg,
+        #[command(flatten)]
+        projection: ProjectionArgs,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = SfsFormat::Tsv)]
+        format: SfsFormat,
+        /// Output file; stdout if omitted
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Blockwise SFS: number of blocks per mutation configuration
+    Blocks {
+        #[command(flatten)]
+        input: InputArgs,
+        /// Block length, in units of --block-unit
+        #[arg(short = 'l', long, value_parser = clap::value_parser!(u64).range(1..))]
+        block_length: u64,
+        /// Measure blocks in base pairs or in sites; with --mask, bp are callable bases, not counting
+        /// positions of dropped sites
+        #[arg(long, value_enum, default_value_t = UnitArg::Bp)]
+        block_unit: UnitArg,
+        /// Maximum span in bp of a block of callable bases (with --mask); default 2 x block length
+        #[arg(long)]
+        max_span: Option<u64>,
+        /// Counts per mutation type above kmax are lumped together
+        #[arg(short, long, default_value_t = 2)]
+        kmax: u64,
+        /// Fold mutation types (ancestral state unknown)
+        #[arg(long, conflicts_with = "four_type")]
+        folded: bool,
+        /// Classify as hetA/hetB/hetAB/fixed; requires one diploid per population in two populations
+        #[arg(long)]
+        four_type: bool,
+        /// Handling of missing calls
+        #[arg(long, value_enum, default_value_t = MissingArg::DropBlock)]
+        missing: MissingArg,
+        /// Output format of the sparse tally
+        #[arg(long, value_enum, default_value_t = TallyFormat::Tsv)]
+        format: TallyFormat,
+        /// Output file; stdout if omitted
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Joint SFS, or bSFS tally with --block-length, per genomic window
+    Windows {
+        #[command(flatten)]
+        input: InputArgs,
+        /// Window size, in units of --window-unit
+        #[arg(short, long, value_parser = clap::value_parser!(u64).range(1..))]
+        size: u64,
+        /// Distance between window starts; default --size (adjacent windows)
+        #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+        step: Option<u64>,
+        /// Measure windows in base pairs or in sites
+        #[arg(long, value_enum, default_value_t = UnitArg::Bp)]
+        window_unit: UnitArg,
+        /// Tally blocks of this length within each window instead of computing the SFS
+        #[arg(short = 'l', long, value_parser = clap::value_parser!(u64).range(1..))]
+        block_length: Option<u64>,
+        /// Measure blocks in base pairs or in sites; bp blocks tile each window from its start,
+        /// with --mask over callable bases, else from the first to the last site of a chromosome
+        #[arg(long, value_enum, default_value_t = UnitArg::Bp)]
+        block_unit: UnitArg,
+        /// Maximum span in bp of a block of callable bases (with --mask); default 2 x block length
+        #[arg(long)]
+        max_span: Option<u64>,
+        /// Counts per mutation type above kmax are lumped together
+        #[arg(short, long, default_value_t = 2)]
+        kmax: u64,
+        /// Fold the spectrum or mutation types (ancestral state unknown)
+        #[arg(long, conflicts_with = "four_type")]
+        folded: bool,
+        /// Classify as hetA/hetB/hetAB/fixed; requires --block-length
+        #[arg(long, requires = "block_length")]
+        four_type: bool,
+        /// Handling of missing calls in blocks; sites with missing calls are dropped for the SFS
+        #[arg(long, value_enum, default_value_t = MissingArg::DropBlock)]
+        missing: MissingArg,
+        /// Output TSV; stdout if omitted
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Diversity, divergence and neutrality statistics per callable base; sites with missing calls
+    /// are dropped unless projected, and positions of dropped sites are not counted as callable
+    Diversity {
+        #[command(flatten)]
+        input: InputArgs,
+        /// Handling of sites with missing calls
+        #[arg(long, value_enum, default_value_t = SiteMissingArg::DropSite)]
+        missing: SiteMissingArg,
+        #[command(flatten)]
+        projection: ProjectionArgs,
+        /// Callable length in bp; default the bases covered by --mask. Positions of sites dropped by
+        /// filters, polarization, the multiallelic policy, missing calls or projection are taken
+        /// off either
+        #[arg(long)]
+        callable_length: Option<u64>,
+        /// Output TSV; stdout if omitted
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Input and filtering statistics
+    Stats {
+        #[command(flatten)]
+        input: InputArgs,
+        /// Output TSV; stdout if omitted
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Args)]
+struct InputArgs {
+    /// VCF or bgzipped VCF
+    #[arg(short, long)]
+    vcf: PathBuf,
+    /// Tab-separated sample_name<TAB>population file; other VCF samples are ignored
+    #[arg(short, long)]
+    popfile: PathBuf,
+    /// BED of callable regions; repeat to intersect several masks
+    #[arg(short, long)]
+    mask: Vec<PathBuf>,
+    /// Handling of sites with more than one alternate allele
+    #[arg(long, value_enum, default_value_t = MultiallelicArg::Skip)]
+    multiallelic: MultiallelicArg,
+    /// Polarize against ancestral sequence FASTA
+    #[arg(long, conflicts_with = "aa_info")]
+    ancestral_fasta: Option<PathBuf>,
+    /// Polarize against the INFO/AA tag
+    #[arg(long)]
+    aa_info: bool,
+    #[command(flatten)]
+    filter: FilterArgs,
+}
+
+#[derive(Args)]
+struct ProjectionArgs {
+    /// Haplotypes per population, in sorted population order, to project down to with --missing
+    /// project; default the size maximising segregating sites in each population
+    #[arg(long, value_delimiter = ',')]
+    projection: Vec<usize>,
+}
+
+/// Genotype filters set failing calls to missing; site filters drop the site
+#[derive(Args)]
+struct FilterArgs {
+    /// Minimum FORMAT/GQ of a call
+    #[arg(long)]
+    min_gq: Option<f64>,
+    /// Minimum FORMAT/DP of a call
+    #[arg(long)]
+    min_dp: Option<u64>,
+    /// Maximum FORMAT/DP of a call
+    #[arg(long)]
+    max_dp: Option<u64>,
+    /// Minimum fraction of FORMAT/AD reads on the minor allele of a heterozygous call
+    #[arg(long)]
+    min_allele_balance: Option<f64>,
+    /// Minimum site QUAL
+    #[arg(long)]
+    min_qual: Option<f64>,
+    /// Keep only sites with FILTER=PASS
+    #[arg(long)]
+    pass_only: bool,
+    /// Minimum mean FORMAT/DP of a site
+    #[arg(long)]
+    min_mean_depth: Option<f64>,
+    /// Maximum mean FORMAT/DP of a site
+    #[arg(long)]
+    max_mean_depth: Option<f64>,
+    /// Maximum fraction of samples with a missing call, after genotype filters
+    #[arg(long)]
+    max_missing: Option<f64>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SfsFormat {
+    /// One row per non-zero entry
+    Tsv,
+    /// dadi/moments `.fs`; --folded follows dadi's `Spectrum.fold`
+    Dadi,
+    /// fastsimcoal2 `.obs` files; --output is the file prefix, and the --mask bases less the
+    /// positions of dropped sites set the monomorphic count
+    Fsc,
+    /// NumPy `.npz` with the SFS and population metadata; requires --output
+    Npz,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum TallyFormat {
+    /// `#key=value` header lines, then one row per observed configuration
+    Tsv,
+    /// Metadata fields and a `configurations` array
+    Json,
+    /// NumPy `.npz` with the dense tally (one axis of size kmax + 2 per mutation type); requires --output
+    Npz,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum UnitArg {
+    Bp,
+    Sites,
+}
+
+impl UnitArg {
+    fn block_length(self, length: u64) -> BlockLength {
+        match self {
+            UnitArg::Bp => BlockLength::Bases(length),
+            UnitArg::Sites => BlockLength::Sites(length as usize),
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum MultiallelicArg {
+    /// Drop multiallelic sites
+    Skip,
+    /// Split into one biallelic site per alternate allele
+    Split,
+    /// Set multiallelic sites aside and report them in `bsfs stats`
+    Separate,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum MissingArg {
+    DropSite,
+    DropBlock,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SiteMissingArg {
+    /// Remove sites with any missing call
+    DropSite,
+    /// Project each site down to --projection haplotypes per population, as easySFS does; sites
+    /// with fewer calls are dropped
+    Project,
+}
+
+impl ProjectionArgs {
+    /// Error if --projection is given without --missing project
+    fn check_unused(&self) -> Result<(), &'static str> {
+        match self.projection.is_empty() {
+            true => Ok(()),
+            false => Err("--projection requires --missing project"),
+        }
+    }
+}
+
+impl MissingArg {
+    fn policy(self) -> MissingPolicy {
+        match self {
+            MissingArg::DropSite => MissingPolicy::DropSite,
+            MissingArg::DropBlock => MissingPolicy::DropBlock,
+        }
+    }
+}
+
+/// Sites and sample map after reading and filtering, with run statistics
+struct Input {
+    sample_map: SampleMap,
+    sites: Vec<Site>,
+    mask: Option<CallableMask>,
+    /// Callable positions left without a site by filters, polarization or the multiallelic policy
+    dropped: Vec<(String, u64)>,
+    stats: InputStats,
+}
+
+impl Input {
+    /// Drop sites with missing calls, adding their positions to `dropped`
+    fn drop_missing(&mut self) {
+        let (missing, kept): (Vec<Site>, Vec<Site>) = std::mem::take(&mut self.sites)
+            .into_iter()
+            .partition(|site| has_missing(&site.calls));
+        self.sites = kept;
+        let positions = missing.into_iter().map(|site| (site.chrom, site.pos));
+        self.dropped = dropped_positions(
+            self.dropped.drain(..).chain(positions).collect(),
+            self.sites
+                .iter()
+                .map(|site| (site.chrom.as_str(), site.pos)),
+        );
+    }
+
+    /// Project every site down to `projection` haplotypes per population, or if empty to the size
+    /// maximising segregating sites in each population; sites with fewer calls are dropped
+    fn project(&mut self, projection: &[usize]) -> Result<ArrayD<f64>, Box<dyn Error>> {
+        let sites = std::mem::take(&mut self.sites);
+        let positions: Vec<(String, u64)> = sites
+            .iter()
+            .map(|site| (site.chrom.clone(), site.pos))
+            .collect();
+        let counts = site_counts(
+            sites.into_iter().map(|site| site.calls).collect(),
+            &self.sample_map,
+        )?;
+
+        let mut target = projection.to_vec();
+        if target.is_empty() {
+            for population in 0..self.sample_map.populations().len() {
+                target.push(best_projection(&counts, population)?.ok_or("no sites to project")?);
+            }
+        }
+        let sfs = project_sites(&counts, &target)?;
+
+        let (kept, skipped): (Vec<_>, Vec<_>) =
+            positions.into_iter().zip(&counts).partition(|(_, site)| {
+                site.called
+                    .iter()
+                    .zip(&target)
+                    .all(|(called, size)| called >= size)
+            });
+        self.dropped = dropped_positions(
+            self.dropped
+                .drain(..)
+                .chain(skipped.into_iter().map(|(position, _)| position))
+                .collect(),
+            kept.iter().map(|((chrom, pos), _)| (chrom.as_str(), *pos)),
+        );
+
+        Ok(sfs)
+    }
+
+    /// Mask without the dropped positions
+    fn callable_mask(&self) -> Option<CallableMask> {
+        self.mask.as_ref().map(|mask| {
+            mask.without_positions(
+                self.dropped
+                    .iter()
+                    .map(|(chrom, pos)| (chrom.as_str(), *pos)),
+            )
+        })
+    }
+
+    /// `length`, or else the bases of the mask, less the dropped positions
+    fn callable_length(&self, length: Option<u64>) -> Option<u64> {
+        match length {
+            Some(length) => Some(length.saturating_sub(self.dropped.len() as u64)),
+            None => self
+                .callable_mask()
+                .as_ref()
+                .map(CallableMask::callable_bases),
+        }
+    }
+}
+
+#[derive(Default)]
+struct InputStats {
+    samples: usize,
+    unknown_samples: Vec<String>,
+    unassigned_samples: Vec<String>,
+    records: u64,
+    filter: FilterStats,
+    multiallelic: MultiallelicStats,
+    polarization: Option<PolarizationStats>,
+    masked_sites: u64,
+}
+
+/// Largest dense tally written by `blocks --format npz` (8 GiB of u64)
+const MAX_DENSE_ENTRIES: usize = 1 << 30;
+
+fn main() {
+    if let Err(e) = run(Cli::parse()) {
+        eprintln!("bsfs: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(cli.threads)
+        .build_global()?;
+
+    match cli.command {
+        Command::Sfs {
+            input,
+            folded,
+            missing,
+            projection,
+            format,
+            output,
+        } => {
+            let mut input = load_input(&input)?;
+            for site in &input.sites {
+                site.check_ploidy(&input.sample_map)?;
+            }
+            let populations = input.sample_map.populations().to_vec();
+            if let SiteMissingArg::Project = missing {
+                let sfs = input.project(&projection.projection)?;
+                match format {
+                    SfsFormat::Tsv => {
+                        let sfs = if folded { fold_matrix(&sfs) } else { sfs };
+                        write_sfs(&mut open_output(&output)?, &populations, &sfs)?
+                    }
+                    SfsFormat::Dadi => {
+                        let spectrum = FsSpectrum::new(sfs, populations);
+                        let spectrum = if folded { spectrum.fold() } else { spectrum };
+                        spectrum.write(open_output(&output)?)?
+                    }
+                    SfsFormat::Fsc | SfsFormat::Npz => {
+                        return Err("--missing project writes --format tsv or dadi".into())
+                    }
+                }
+                return Ok(());
+            }
+            projection.check_unused()?;
+            input.drop_missing();
+            let callable_sites = input.callable_length(None);
+            let calls: Vec<Vec<Vec<u32>>> =
+                input.sites.into_iter().map(|site| site.calls).collect();
+
+            let mut sfs = par_bsfs_matrix(calls, &input.sample_map)?;
+            if let (SfsFormat::Fsc, Some(prefix)) = (format, &output) {
+                write_fsc_files(&prefix.to_string_lossy(), &sfs, callable_sites, folded)?;
+                return Ok(());
+            }
+            // dadi splits entries with half of the haplotypes derived, so `.fs` output folds itself
+            if folded && !matches!(format, SfsFormat::Dadi) {
+                sfs = fold_matrix(&sfs);
+            }
+
+            match format {
+                SfsFormat::Tsv => write_sfs(&mut open_output(&output)?, &populations, &sfs)?,
+                SfsFormat::Dadi => {
+                    let spectrum = FsSpectrum::new(sfs.mapv(|count| count as f64), populations);
+                    let spectrum = if folded { spectrum.fold() } else { spectrum };
+                    spectrum.write(open_output(&output)?)?
+                }
+                SfsFormat::Fsc => return Err("--format fsc requires --output prefix".into()),
+                SfsFormat::Npz => NpzSpectrum::from_sfs(sfs, populations)
+                    .to_path(output.ok_or("--format npz requires --output")?)?,
+            }
+        }
+        Command::Blocks {
+            input,
+            block_length,
+            block_unit,
+            max_span,
+            kmax,
+            folded,
+            four_type,
+            missing,
+            format,
+            output,
+        } => {
+            let input = load_input(&input)?;
+            let shape = input.sample_map.sfs_shape();
+            // Dropped variant positions are not monomorphic bases
+            let mask = input.callable_mask();
+
+            let block_length = block_unit.block_length(block_length);
+
+            let blocks = match (&mask, block_length) {
+                (Some(mask), BlockLength::Bases(length)) => {
+                    make_callable_blocks(input.sites, mask, length, max_span.unwrap_or(2 * length))?
+                }
+                _ => make_blocks(input.sites, block_length)?,
+            };
+            let blocks = apply_missing_policy(blocks, missing.policy());
+
+            let columns = mutation_types(&shape, folded, four_type);
+            let kmax = vec![kmax; columns.len()];
+            let tally = tally_blocks(blocks, &input.sample_map, kmax, folded, four_type)?;
+
+            let sparse = SparseTally {
+                metadata: TallyMetadata {
+                    populations: input.sample_map.populations().to_vec(),
+                    sample_sizes: shape.iter().map(|dim| dim - 1).collect(),
+                    block_length,
+                    masked: mask.is_some(),
+                    kmax: tally.kmax().to_vec(),
+                    folded,
+                    four_type,
+                    mutation_types: columns,
+                },
+                tally,
+            };
+            match format {
+                TallyFormat::Tsv => sparse.write_tsv(open_output(&output)?)?,
+                TallyFormat::Json => sparse.write_json(open_output(&output)?)?,
+                TallyFormat::Npz => {
+                    let output = output.ok_or("--format npz requires --output")?;
+                    let fits = sparse
+                        .tally
+                        .kmax()
+                        .iter()
+                        .try_fold(1usize, |n, kmax| n.checked_mul(*kmax as usize + 2))
+                        .is_some_and(|n| n <= MAX_DENSE_ENTRIES);
+                    if !fits {
+                        return Err(
+                            "dense tally too large for --format npz; use tsv or json".into()
+                        );
+                    }
+                    let metadata = sparse.metadata;
+                    NpzSpectrum::from_tally(
+                        &sparse.tally,
+                        metadata.populations,
+                        metadata.sample_sizes,
+                    )
+                    .to_path(output)?
+                }
+            }
+        }
+        Command::Windows {
+            input,
+            size,
+            step,
+            window_unit,
+            block_length,
+            block_unit,
+            max_span,
+            kmax,
+            folded,
+            four_type,
+            missing,
+            output,
+        } => {
+            let input = load_input(&input)?;
+            let step = step.unwrap_or(size);
+            let spec = match window_unit {
+                UnitArg::Bp => WindowSpec::Bases { size, step },
+                UnitArg::Sites => WindowSpec::Sites {
+                    size: size as usize,
+                    step: step as usize,
+                },
+            };
+
+            match block_length {
+                None => {
+                    let sites: Vec<Site> = input
+                        .sites
+                        .into_iter()
+                        .filter(|site| !has_missing(&site.calls))
+                        .collect();
+                    let mut spectra =
+                        window_spectra(&make_windows(&sites, spec)?, &input.sample_map)?;
+                    if folded {
+                        for spectrum in &mut spectra {
+                            spectrum.sfs = fold_matrix(&spectrum.sfs);
+                        }
+                    }
+                    write_window_spectra(open_output(&output)?, &spectra)?;
+                }
+                Some(block_length) => {
+                    let columns = mutation_types(&input.sample_map.sfs_shape(), folded, four_type);
+                    let mask = input.callable_mask();
+                    let tallies = window_tallies(&make_windows(&input.sites, spec)?, |window| {
+                        let blocks = match block_unit.block_length(block_length) {
+                            BlockLength::Bases(length) => window_blocks(
+                                window,
+                                mask.as_ref(),
+                                length,
+                                max_span.unwrap_or(2 * length),
+                            )?,
+                            block_length => {
+                                make_blocks(window.sites.iter().cloned(), block_length)?
+                            }
+                        };
+                        let blocks = apply_missing_policy(blocks, missing.policy());
+                        tally_blocks(
+                            blocks,
+                            &input.sample_map,
+                            vec![kmax; columns.len()],
+                            folded,
+                            four_type,
+                        )
+                    })?;
+                    write_window_tallies(open_output(&output)?, &columns, &tallies)?;
+                }
+            }
+        }
+        Command::Diversity {
+            input,
+            missing,
+            projection,
+            callable_length,
+            output,
+        } => {
+            let polarized = input.ancestral_fasta.is_some() || input.aa_info;
+            let mut input = load_input(&input)?;
+            let populations = input.sample_map.populations().to_vec();
+            let callable = |input: &Input| {
+                input
+                    .callable_length(callable_length)
+                    .ok_or("diversity requires --mask or --callable-length")
+            };
+
+            let stats = match missing {
+                SiteMissingArg::DropSite => {
+                    projection.check_unused()?;
+                    input.drop_missing();
+                    let length = callable(&input)?;
+                    let calls: Vec<Vec<Vec<u32>>> =
+                        input.sites.into_iter().map(|site| site.calls).collect();
+                    let sfs = par_bsfs_matrix(calls, &input.sample_map)?;
+                    summary_stats(&sfs, &populations, length, polarized)?
+                }
+                SiteMissingArg::Project => {
+                    let sfs = input.project(&projection.projection)?;
+                    summary_stats(&sfs, &populations, callable(&input)?, polarized)?
+                }
+            };
+            stats.write_tsv(open_output(&output)?)?;
+        }
+        Command::Stats { input, output } => {
+            let input = load_input(&input)?;
+            write_stats(&mut open_output(&output)?, &input)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Read VCF, restrict to popfile samples, then filter, polarize, resolve multiallelic sites and
+/// mask; the whole VCF is held in memory
+fn load_input(args: &InputArgs) -> Result<Input, Box<dyn Error>> {
+    let reader = VcfReader::from_path(&args.vcf)?;
+    let samples = reader.samples().to_vec();
+    let assignments = parse_popfile(BufReader::new(File::open(&args.popfile)?))?;
+
+    let kept: Vec<usize> = (0..samples.len())
+        .filter(|idx| {
+            assignments
+                .iter()
+                .any(|(sample, _)| *sample == samples[*idx])
+        })
+        .collect();
+    let mut records: Vec<VcfRecord> = reader
+        .map(|record| {
+            record.map(|mut record| {
+                record.select_samples(&kept);
+                record
+            })
+        })
+        .collect::<Result<_, _>>()?;
+
+    let positions: Vec<(String, u64)> = records
+        .iter()
+        .map(|record| (record.chrom.clone(), record.pos))
+        .collect();
+    let kept_samples: Vec<String> = kept.iter().map(|idx| samples[*idx].clone()).collect();
+    let ploidies = infer_ploidies(
+        records.iter().map(|record| record.calls.as_slice()),
+        kept.len(),
+    );
+    for record in &mut records {
+        record.expand_missing(&ploidies);
+    }
+    let assignment = assign_haplotypes(&assignments, &kept_samples, &ploidies)?;
+    assignment.sample_map.validate()?;
+
+    let mut stats = InputStats {
+        samples: samples.len(),
+        unknown_samples: assignment.unknown_samples,
+        unassigned_samples: samples
+            .iter()
+            .filter(|sample| !kept_samples.contains(sample))
+            .cloned()
+            .collect(),
+        records: records.len() as u64,
+        ..Default::default()
+    };
+
+    let filter = RecordFilter {
+        genotype: GenotypeFilter {
+            min_gq: args.filter.min_gq,
+            min_dp: args.filter.min_dp,
+            max_dp: args.filter.max_dp,
+            min_allele_balance: args.filter.min_allele_balance,
+        },
+        site: SiteFilter {
+            min_qual: args.filter.min_qual,
+            pass_only: args.filter.pass_only,
+            min_mean_depth: args.filter.min_mean_depth,
+            max_mean_depth: args.filter.max_mean_depth,
+            max_missing: args.filter.max_missing,
+        },
+    };
+    let outcome = filter_records(records, &filter);
+    stats.filter = outcome.stats;
+    let records = outcome.records;
+
+    let source = match (&args.ancestral_fasta, args.aa_info) {
+        (Some(path), _) => Some(AncestralSource::Fasta(AncestralFasta::from_path(path)?)),
+        (None, true) => Some(AncestralSource::InfoAa),
+        (None, false) => None,
+    };
+    let sites: Vec<Site> = match source {
+        Some(source) => {
+            let outcome = polarize_records(records, &source);
+            stats.polarization = Some(outcome.stats);
+            outcome.sites
+        }
+        None => records.into_iter().map(Site::from).collect(),
+    };
+
+    let outcome = apply_multiallelic_policy(
+        sites,
+        match args.multiallelic {
+            MultiallelicArg::Skip => MultiallelicPolicy::Skip,
+            MultiallelicArg::Split => MultiallelicPolicy::Split,
+            MultiallelicArg::Separate => MultiallelicPolicy::Separate,
+        },
+    );
+    stats.multiallelic = outcome.stats;
+
+    let (sites, mask) = if args.mask.is_empty() {
+        (outcome.sites, None)
+    } else {
+        let mask = CallableMask::from_bed_paths(&args.mask)?;
+        let n_sites = outcome.sites.len();
+        let sites = filter_sites(outcome.sites, &mask);
+        stats.masked_sites = (n_sites - sites.len()) as u64;
+        (sites, Some(mask))
+    };
+
+    let positions = match &mask {
+        Some(mask) => positions
+            .into_iter()
+            .filter(|(chrom, pos)| mask.contains(chrom, *pos))
+            .collect(),
+        None => positions,
+    };
+
+    Ok(Input {
+        sample_map: assignment.sample_map,
+        dropped: dropped_positions(
+            positions,
+            sites.iter().map(|site| (site.chrom.as_str(), site.pos)),
+        ),
+        sites,
+        mask,
+        stats,
+    })
+}
+
+/// Distinct `positions` not among the `kept` positions of sites
+fn dropped_positions<'a>(
+    positions: Vec<(String, u64)>,
+    kept: impl IntoIterator<Item = (&'a str, u64)>,
+) -> Vec<(String, u64)> {
+    let kept: HashSet<(&str, u64)> = kept.into_iter().collect();
+
+    positions
+        .into_iter()
+        .filter(|(chrom, pos)| !kept.contains(&(chrom.as_str(), *pos)))
+        .unique()
+        .collect()
+}
+
+/// Labels of the mutation types of a bSFS tally: four types, folded or unfolded SFS entries
+fn mutation_types(shape: &[usize], folded: bool, four_type: bool) -> Vec<String> {
+    if four_type {
+        ["hetA", "hetB", "hetAB", "fixed"]
+            .map(String::from)
+            .to_vec()
+    } else if folded {
+        folded_mutation_types(shape)
+            .iter()
+            .map(|entry| entry.iter().join("_"))
+            .collect()
+    } else {
+        ArrayD::<u8>::zeros(IxDyn(shape))
+            .indexed_iter()
+            .map(|(entry, _)| entry.slice().iter().join("_"))
+            .skip(1)
+            .take(n_mutation_types(shape))
+            .collect()
+    }
+}
+
+/// Tally blocks by the mutation types of `mutation_types`
+fn tally_blocks(
+    blocks: Vec<Block>,
+    sample_map: &SampleMap,
+    kmax: Vec<u64>,
+    folded: bool,
+    four_type: bool,
+) -> Result<BlockTally, BsfsError> {
+    if four_type {
+        par_tally_blocks_four_type(blocks, sample_map, kmax)
+    } else if folded {
+        par_tally_blocks_folded(blocks, sample_map, kmax)
+    } else {
+        par_tally_blocks(blocks, sample_map, kmax)
+    }
+}
+
+fn open_output(path: &Option<PathBuf>) -> io::Result<Box<dyn Write>> {
+    Ok(match path {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout())),
+    })
+}
+
+/// One row per non-zero joint SFS entry; counts of a projected SFS are expected counts
+fn write_sfs<T: Count + Display>(
+    out: &mut dyn Write,
+    populations: &[String],
+    sfs: &ArrayD<T>,
+) -> io::Result<()> {
+    writeln!(out, "{}\tcount", populations.join("\t"))?;
+    for (entry, count) in sfs
+        .indexed_iter()
+        .filter(|(_, count)| **count > T::default())
+    {
+        writeln!(out, "{}\t{}", entry.slice().iter().join("\t"), count)?;
+    }
+
+    out.flush()
+}
+
+fn write_stats(out: &mut dyn Write, input: &Input) -> io::Result<()> {
+    let stats = &input.stats;
+    writeln!(out, "vcf_samples\t{}", stats.samples)?;
+    writeln!(
+        out,
+        "unassigned_samples\t{}",
+        stats.unassigned_samples.join(",")
+    )?;
+    writeln!(out, "unknown_samples\t{}", stats.unknown_samples.join(","))?;
+    let sample_map = &input.sample_map;
+    for (population, n_haps) in sample_map
+        .populations()
+        .iter()
+        .zip(sample_map.haps_per_pop())
+    {
+        writeln!(out, "haplotypes_{}\t{}", population, n_haps)?;
+    }
+    writeln!(out, "records\t{}", stats.records)?;
+    writeln!(out, "filtered_qual\t{}", stats.filter.failed_qual)?;
+    writeln!(out, "filtered_filter\t{}", stats.filter.failed_filter)?;
+    writeln!(out, "filtered_depth\t{}", stats.filter.failed_depth)?;
+    writeln!(out, "filtered_missing\t{}", stats.filter.failed_missing)?;
+    writeln!(out, "masked_genotypes\t{}", stats.filter.masked_genotypes)?;
+    if let Some(polarization) = stats.polarization {
+        writeln!(out, "ref_ancestral\t{}", polarization.ref_ancestral)?;
+        writeln!(out, "flipped\t{}", polarization.flipped)?;
+        writeln!(out, "unpolarized\t{}", polarization.unpolarized)?;
+    }
+    writeln!(out, "biallelic\t{}", stats.multiallelic.biallelic)?;
+    writeln!(out, "multiallelic_skipped\t{}", stats.multiallelic.skipped)?;
+    writeln!(out, "multiallelic_split\t{}", stats.multiallelic.split)?;
+    writeln!(out, "split_records\t{}", stats.multiallelic.split_records)?;
+    writeln!(
+        out,
+        "multiallelic_separated\t{}",
+        stats.multiallelic.separated
+    )?;
+    if let Some(mask) = &input.mask {
+        writeln!(out, "masked_sites\t{}", stats.masked_sites)?;
+        writeln!(out, "callable_bases\t{}", mask.callable_bases())?;
+    }
+    writeln!(out, "sites\t{}", input.sites.len())?;
+    writeln!(
+        out,
+        "sites_with_missing\t{}",
+        input
+            .sites
+            .iter()
+            .filter(|site| has_missing(&site.calls))
+            .count()
+    )?;
+
+    out.flush()
+}
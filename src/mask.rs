@@ -0,0 +1,302 @@
+use crate::{error::BsfsError, Site};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+const FORMAT: &str = "BED";
+
+/// Callable regions per chromosome; sorted, non-overlapping, 0-based half-open intervals
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CallableMask {
+    chroms: Vec<String>,
+    regions: HashMap<String, Vec<(u64, u64)>>,
+}
+
+impl CallableMask {
+    /// Parse BED records; `track`, `browser` and `#` lines are skipped, overlapping intervals merged
+    pub fn from_bed<R: BufRead>(reader: R) -> Result<Self, BsfsError> {
+        let mut mask = CallableMask::default();
+
+        for (idx, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty()
+                || line.starts_with('#')
+                || line.starts_with("track")
+                || line.starts_with("browser")
+            {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split('\t').collect();
+            let interval = match fields[..] {
+                [_, start, end, ..] => start.parse::<u64>().ok().zip(end.parse::<u64>().ok()),
+                _ => None,
+            };
+            let (start, end) = match interval {
+                Some((start, end)) if start <= end => (start, end),
+                _ => {
+                    return Err(BsfsError::parse(
+                        FORMAT,
+                        idx + 1,
+                        "expected 'chrom<TAB>start<TAB>end'",
+                    ))
+                }
+            };
+
+            if !mask.regions.contains_key(fields[0]) {
+                mask.chroms.push(fields[0].to_string());
+            }
+            mask.regions
+                .entry(fields[0].to_string())
+                .or_default()
+                .push((start, end));
+        }
+
+        for intervals in mask.regions.values_mut() {
+            *intervals = merge_intervals(std::mem::take(intervals));
+        }
+
+        Ok(mask)
+    }
+
+    /// Mask with the single callable interval `[start, end)` on `chrom`
+    pub fn span(chrom: &str, start: u64, end: u64) -> Self {
+        CallableMask {
+            chroms: vec![chrom.to_string()],
+            regions: HashMap::from([(chrom.to_string(), merge_intervals(vec![(start, end)]))]),
+        }
+    }
+
+    /// Load BED files at `paths`; a base is callable only if it is callable in every file
+    pub fn from_bed_paths<P: AsRef<Path>>(paths: &[P]) -> Result<Self, BsfsError> {
+        let mut masks = paths
+            .iter()
+            .map(|path| CallableMask::from_bed(BufReader::new(File::open(path)?)));
+
+        let first = masks
+            .next()
+            .unwrap_or_else(|| Ok(CallableMask::default()))?;
+        masks.try_fold(first, |mask, other| Ok(mask.intersect(&other?)))
+    }
+
+    /// Regions callable in both masks
+    pub fn intersect(&self, other: &CallableMask) -> CallableMask {
+        let mut mask = CallableMask::default();
+
+        for chrom in &self.chroms {
+            let intervals = intersect_intervals(self.regions(chrom), other.regions(chrom));
+            if !intervals.is_empty() {
+                mask.chroms.push(chrom.to_string());
+                mask.regions.insert(chrom.to_string(), intervals);
+            }
+        }
+
+        mask
+    }
+
+    /// Mask with the 1-based `positions` made uncallable, e.g. those of sites dropped by filters
+    pub fn without_positions<'a>(
+        &self,
+        positions: impl IntoIterator<Item = (&'a str, u64)>,
+    ) -> CallableMask {
+        let mut removed: HashMap<&str, Vec<(u64, u64)>> = HashMap::new();
+        for (chrom, pos) in positions {
+            removed.entry(chrom).or_default().push((pos - 1, pos));
+        }
+
+        let mut mask = CallableMask::default();
+        for chrom in &self.chroms {
+            let intervals = match removed.remove(chrom.as_str()) {
+                Some(positions) => {
+                    subtract_intervals(self.regions(chrom), &merge_intervals(positions))
+                }
+                None => self.regions(chrom).to_vec(),
+            };
+            if !intervals.is_empty() {
+                mask.chroms.push(chrom.to_string());
+                mask.regions.insert(chrom.to_string(), intervals);
+            }
+        }
+
+        mask
+    }
+
+    /// Chromosomes in order of first appearance
+    pub fn chroms(&self) -> &[String] {
+        &self.chroms
+    }
+
+    /// Callable intervals on `chrom`
+    pub fn regions(&self, chrom: &str) -> &[(u64, u64)] {
+        self.regions.get(chrom).map_or(&[], |intervals| intervals)
+    }
+
+    /// Whether 1-based position `pos` on `chrom` is callable
+    pub fn contains(&self, chrom: &str, pos: u64) -> bool {
+        let intervals = self.regions(chrom);
+        let idx = intervals.partition_point(|(_, end)| *end < pos);
+
+        idx < intervals.len() && intervals[idx].0 < pos
+    }
+
+    /// Total number of callable bases
+    pub fn callable_bases(&self) -> u64 {
+        self.regions
+            .values()
+            .flatten()
+            .map(|(start, end)| end - start)
+            .sum()
+    }
+}
+
+/// Drop sites outside callable regions
+pub fn filter_sites(sites: Vec<Site>, mask: &CallableMask) -> Vec<Site> {
+    sites
+        .into_iter()
+        .filter(|site| mask.contains(&site.chrom, site.pos))
+        .collect()
+}
+
+fn merge_intervals(mut intervals: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    intervals.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = vec![];
+
+    for (start, end) in intervals {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+
+    merged.retain(|(start, end)| start < end);
+    merged
+}
+
+fn intersect_intervals(a: &[(u64, u64)], b: &[(u64, u64)]) -> Vec<(u64, u64)> {
+    let mut intervals: Vec<(u64, u64)> = vec![];
+    let (mut i, mut j) = (0, 0);
+
+    while i < a.len() && j < b.len() {
+        let start = a[i].0.max(b[j].0);
+        let end = a[i].1.min(b[j].1);
+        if start < end {
+            intervals.push((start, end));
+        }
+        if a[i].1 < b[j].1 {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+
+    intervals
+}
+
+/// Parts of the intervals `a` not covered by `b`; both sorted and non-overlapping
+fn subtract_intervals(a: &[(u64, u64)], b: &[(u64, u64)]) -> Vec<(u64, u64)> {
+    let mut intervals: Vec<(u64, u64)> = vec![];
+    let mut j = 0;
+
+    for &(mut start, end) in a {
+        while j < b.len() && b[j].1 <= start {
+            j += 1;
+        }
+        let mut k = j;
+        while k < b.len() && b[k].0 < end {
+            if start < b[k].0 {
+                intervals.push((start, b[k].0));
+            }
+            start = start.max(b[k].1);
+            k += 1;
+        }
+        if start < end {
+            intervals.push((start, end));
+        }
+    }
+
+    intervals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::{fixture, rstest};
+    use std::io::Cursor;
+
+    #[fixture]
+    fn mask() -> CallableMask {
+        CallableMask::from_bed(Cursor::new(
+            "track name=callable\nchr1\t0\t10\nchr1\t5\t20\nchr1\t30\t40\nchr2\t100\t200\n",
+        ))
+        .unwrap()
+    }
+
+    #[rstest]
+    fn test_from_bed(mask: CallableMask) {
+        assert_eq!(mask.chroms(), ["chr1", "chr2"]);
+        assert_eq!(mask.regions("chr1"), [(0, 20), (30, 40)]);
+        assert_eq!(mask.callable_bases(), 130);
+    }
+
+    #[rstest]
+    fn test_from_bed_malformed() {
+        let err = CallableMask::from_bed(Cursor::new("chr1\t0\t10\nchr1\t20\n")).unwrap_err();
+
+        assert!(err.to_string().contains("line 2"));
+    }
+
+    #[rstest]
+    fn test_contains(mask: CallableMask) {
+        assert!(mask.contains("chr1", 1));
+        assert!(mask.contains("chr1", 20));
+        assert!(!mask.contains("chr1", 21));
+        assert!(mask.contains("chr1", 31));
+        assert!(!mask.contains("chr3", 1));
+    }
+
+    #[rstest]
+    fn test_intersect(mask: CallableMask) {
+        let other = CallableMask::from_bed(Cursor::new("chr1\t15\t35\n")).unwrap();
+        let intersection = mask.intersect(&other);
+
+        assert_eq!(intersection.chroms(), ["chr1"]);
+        assert_eq!(intersection.regions("chr1"), [(15, 20), (30, 35)]);
+        assert_eq!(
+            CallableMask::span("chr1", 10, 32)
+                .intersect(&mask)
+                .regions("chr1"),
+            [(10, 20), (30, 32)]
+        );
+    }
+
+    #[rstest]
+    fn test_without_positions(mask: CallableMask) {
+        let removed =
+            mask.without_positions([("chr1", 1), ("chr1", 15), ("chr1", 40), ("chr3", 5)]);
+
+        assert_eq!(removed.regions("chr1"), [(1, 14), (15, 20), (30, 39)]);
+        assert_eq!(removed.regions("chr2"), [(100, 200)]);
+        assert_eq!(removed.callable_bases(), 127);
+    }
+
+    #[rstest]
+    fn test_filter_sites(mask: CallableMask) {
+        let sites: Vec<Site> = [10, 25, 35]
+            .into_iter()
+            .map(|pos| Site {
+                chrom: "chr1".to_string(),
+                pos,
+                calls: vec![vec![0, 1]],
+            })
+            .collect();
+        let positions: Vec<u64> = filter_sites(sites, &mask)
+            .iter()
+            .map(|site| site.pos)
+            .collect();
+
+        assert_eq!(positions, vec![10, 35])
+    }
+}
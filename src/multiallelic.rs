@@ -0,0 +1,187 @@
+use crate::{genotype::Genotype, Site};
+use itertools::Itertools;
+
+/// How sites with more than one alternate allele are treated before `site_to_entry`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MultiallelicPolicy {
+    /// Drop multiallelic sites
+    Skip,
+    /// Split into one biallelic record per alternate allele, as `bcftools norm -m-`
+    Split,
+    /// Set multiallelic sites aside as a separate category
+    Separate,
+}
+
+/// Run statistics for multiallelic handling
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MultiallelicStats {
+    /// Sites with at most one alternate allele
+    pub biallelic: u64,
+    /// Multiallelic sites dropped
+    pub skipped: u64,
+    /// Multiallelic sites split
+    pub split: u64,
+    /// Biallelic records produced by splitting
+    pub split_records: u64,
+    /// Multiallelic sites set aside
+    pub separated: u64,
+}
+
+/// Sites after applying a multiallelic policy
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MultiallelicOutcome {
+    /// Biallelic (or split) sites, ready for `site_to_entry`
+    pub sites: Vec<Site>,
+    /// Multiallelic sites under `MultiallelicPolicy::Separate`
+    pub multiallelic: Vec<Site>,
+    pub stats: MultiallelicStats,
+}
+
+/// Distinct alternate alleles among called haplotypes, in ascending order
+pub fn alt_alleles(calls: &[Vec<u32>]) -> Vec<u32> {
+    calls
+        .iter()
+        .flatten()
+        .filter_map(|allele| match Genotype::from(*allele) {
+            Genotype::Alt(n) => Some(n),
+            _ => None,
+        })
+        .sorted()
+        .dedup()
+        .collect()
+}
+
+/// Whether more than one alternate allele is called at the site
+pub fn is_multiallelic(calls: &[Vec<u32>]) -> bool {
+    alt_alleles(calls).len() > 1
+}
+
+/// Split calls into one biallelic record per alternate allele
+///
+/// In the record for allele `n`, `n` becomes 1 and every other called allele becomes 0.
+pub fn split_calls(calls: &[Vec<u32>]) -> Vec<Vec<Vec<u32>>> {
+    alt_alleles(calls)
+        .into_iter()
+        .map(|alt| {
+            calls
+                .iter()
+                .map(|individual| {
+                    individual
+                        .iter()
+                        .map(|allele| match Genotype::from(*allele) {
+                            Genotype::Alt(n) if n == alt => 1,
+                            Genotype::Missing => *allele,
+                            _ => 0,
+                        })
+                        .collect()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Apply multiallelic policy to sites, recording how many were skipped, split or set aside
+pub fn apply_multiallelic_policy(
+    sites: Vec<Site>,
+    policy: MultiallelicPolicy,
+) -> MultiallelicOutcome {
+    let mut outcome = MultiallelicOutcome::default();
+
+    for site in sites {
+        if !is_multiallelic(&site.calls) {
+            outcome.stats.biallelic += 1;
+            outcome.sites.push(site);
+            continue;
+        }
+
+        match policy {
+            MultiallelicPolicy::Skip => outcome.stats.skipped += 1,
+            MultiallelicPolicy::Split => {
+                outcome.stats.split += 1;
+                for calls in split_calls(&site.calls) {
+                    outcome.stats.split_records += 1;
+                    outcome.sites.push(Site {
+                        chrom: site.chrom.clone(),
+                        pos: site.pos,
+                        calls,
+                    });
+                }
+            }
+            MultiallelicPolicy::Separate => {
+                outcome.stats.separated += 1;
+                outcome.multiallelic.push(site);
+            }
+        }
+    }
+
+    outcome
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vcf::MISSING;
+    use rstest::{fixture, rstest};
+
+    #[fixture]
+    fn sites() -> Vec<Site> {
+        [
+            vec![vec![0, 1], vec![1, 1]],
+            vec![vec![0, 2], vec![1, MISSING]],
+            vec![vec![0, 0], vec![2, 2]],
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(idx, calls)| Site {
+            chrom: "chr1".to_string(),
+            pos: idx as u64 + 1,
+            calls,
+        })
+        .collect()
+    }
+
+    #[rstest]
+    fn test_is_multiallelic() {
+        assert!(is_multiallelic(&[vec![0, 1], vec![2, 0]]));
+        assert!(!is_multiallelic(&[vec![0, 2], vec![2, MISSING]]));
+    }
+
+    #[rstest]
+    fn test_split_calls() {
+        let split = split_calls(&[vec![0, 2], vec![1, MISSING]]);
+        let expected = vec![
+            vec![vec![0, 0], vec![1, MISSING]],
+            vec![vec![0, 1], vec![0, MISSING]],
+        ];
+
+        assert_eq!(split, expected)
+    }
+
+    #[rstest]
+    fn test_apply_multiallelic_policy_skip(sites: Vec<Site>) {
+        let outcome = apply_multiallelic_policy(sites, MultiallelicPolicy::Skip);
+
+        assert_eq!(outcome.sites.len(), 2);
+        assert_eq!(outcome.stats.biallelic, 2);
+        assert_eq!(outcome.stats.skipped, 1);
+    }
+
+    #[rstest]
+    fn test_apply_multiallelic_policy_split(sites: Vec<Site>) {
+        let outcome = apply_multiallelic_policy(sites, MultiallelicPolicy::Split);
+        let positions: Vec<u64> = outcome.sites.iter().map(|site| site.pos).collect();
+
+        assert_eq!(positions, vec![1, 2, 2, 3]);
+        assert_eq!(outcome.stats.split, 1);
+        assert_eq!(outcome.stats.split_records, 2);
+    }
+
+    #[rstest]
+    fn test_apply_multiallelic_policy_separate(sites: Vec<Site>) {
+        let outcome = apply_multiallelic_policy(sites, MultiallelicPolicy::Separate);
+
+        assert_eq!(outcome.sites.len(), 2);
+        assert_eq!(outcome.multiallelic.len(), 1);
+        assert_eq!(outcome.stats.separated, 1);
+    }
+}
@@ -0,0 +1,458 @@
+use crate::{error::BsfsError, tally::BlockTally};
+use ndarray::{ArrayD, IxDyn, ShapeBuilder};
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Seek, Write},
+    path::Path,
+};
+use zip::{result::ZipError, write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+const FORMAT: &str = ".npy";
+const MAGIC: &[u8] = b"\x93NUMPY";
+
+/// Write `u64` array in NumPy `.npy` format (`<u8`, C order)
+pub fn write_npy<W: Write>(mut out: W, array: &ArrayD<u64>) -> io::Result<()> {
+    write_header(&mut out, "<u8", array.shape())?;
+    for value in array.iter() {
+        out.write_all(&value.to_le_bytes())?;
+    }
+
+    out.flush()
+}
+
+/// Read `.npy` array of unsigned or non-negative signed 64-bit integers
+pub fn read_npy<R: Read>(mut reader: R) -> Result<ArrayD<u64>, BsfsError> {
+    let header = read_header(&mut reader)?;
+    let signed = match header.descr.as_str() {
+        "<u8" => false,
+        "<i8" => true,
+        descr => {
+            return Err(BsfsError::parse(
+                FORMAT,
+                0,
+                format!("unsupported dtype '{}'", descr),
+            ))
+        }
+    };
+
+    // Check the shape against the data actually present before allocating for it
+    let mut bytes: Vec<u8> = vec![];
+    reader.read_to_end(&mut bytes)?;
+    let n_values = header
+        .shape
+        .iter()
+        .try_fold(1usize, |n, dim| n.checked_mul(*dim))
+        .filter(|n| n.checked_mul(8).is_some_and(|len| len <= bytes.len()))
+        .ok_or_else(|| {
+            BsfsError::parse(
+                FORMAT,
+                0,
+                format!(
+                    "shape {:?} needs more than the {} bytes of data",
+                    header.shape,
+                    bytes.len()
+                ),
+            )
+        })?;
+
+    let data: Vec<u64> = bytes
+        .chunks_exact(8)
+        .take(n_values)
+        .map(|chunk| {
+            let value: [u8; 8] = chunk.try_into().expect("8 bytes");
+            if signed && i64::from_le_bytes(value) < 0 {
+                return Err(BsfsError::parse(FORMAT, 0, "negative count"));
+            }
+            Ok(u64::from_le_bytes(value))
+        })
+        .collect::<Result<_, _>>()?;
+
+    header.into_array(data)
+}
+
+/// Write strings as a NumPy unicode (`<U`) array, loadable without pickle
+pub fn write_npy_strings<W: Write>(mut out: W, strings: &[String]) -> io::Result<()> {
+    let width = strings
+        .iter()
+        .map(|string| string.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    write_header(&mut out, &format!("<U{}", width), &[strings.len()])?;
+    for string in strings {
+        let n_chars = string.chars().count();
+        for c in string.chars() {
+            out.write_all(&(c as u32).to_le_bytes())?;
+        }
+        out.write_all(&vec![0u8; 4 * (width - n_chars)])?;
+    }
+
+    out.flush()
+}
+
+/// Read one-dimensional NumPy unicode (`<U`) array
+pub fn read_npy_strings<R: Read>(mut reader: R) -> Result<Vec<String>, BsfsError> {
+    let header = read_header(&mut reader)?;
+    let width: usize = header
+        .descr
+        .strip_prefix("<U")
+        .and_then(|width| width.parse().ok())
+        .ok_or_else(|| {
+            BsfsError::parse(FORMAT, 0, format!("unsupported dtype '{}'", header.descr))
+        })?;
+    if header.shape.len() != 1 {
+        return Err(BsfsError::parse(
+            FORMAT,
+            0,
+            "expected one-dimensional string array",
+        ));
+    }
+
+    let mut bytes = vec![0u8; 4 * width];
+    (0..header.shape[0])
+        .map(|_| {
+            reader.read_exact(&mut bytes)?;
+            bytes
+                .chunks_exact(4)
+                .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+                .take_while(|code| *code != 0)
+                .map(|code| {
+                    char::from_u32(code)
+                        .ok_or_else(|| BsfsError::parse(FORMAT, 0, "invalid character"))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Joint SFS or dense bSFS tally with its metadata, stored as a NumPy `.npz` archive
+///
+/// The archive holds `data.npy` plus `populations.npy`, `sample_sizes.npy` and `kmax.npy`;
+/// `kmax` is empty for an SFS.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NpzSpectrum {
+    pub data: ArrayD<u64>,
+    pub populations: Vec<String>,
+    pub sample_sizes: Vec<usize>,
+    pub kmax: Vec<u64>,
+}
+
+impl NpzSpectrum {
+    /// Wrap joint SFS, e.g. from `bsfs_matrix`; sample sizes follow from its shape
+    pub fn from_sfs(data: ArrayD<u64>, populations: Vec<String>) -> Self {
+        let sample_sizes = data.shape().iter().map(|dim| dim - 1).collect();
+
+        NpzSpectrum {
+            data,
+            populations,
+            sample_sizes,
+            kmax: vec![],
+        }
+    }
+
+    /// Wrap bSFS tally as its dense array (see `BlockTally::to_dense`)
+    pub fn from_tally(
+        tally: &BlockTally,
+        populations: Vec<String>,
+        sample_sizes: Vec<usize>,
+    ) -> Self {
+        NpzSpectrum {
+            data: tally.to_dense(),
+            populations,
+            sample_sizes,
+            kmax: tally.kmax().to_vec(),
+        }
+    }
+
+    /// bSFS tally from the dense array; None for an SFS archive
+    pub fn to_tally(&self) -> Option<BlockTally> {
+        if self.kmax.is_empty() {
+            None
+        } else {
+            Some(BlockTally::from_dense(&self.data))
+        }
+    }
+
+    /// Write uncompressed `.npz` archive, as `numpy.savez` does
+    pub fn write<W: Write + Seek>(&self, out: W) -> io::Result<()> {
+        let mut zip = ZipWriter::new(out);
+        let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+        let sample_sizes: Vec<u64> = self.sample_sizes.iter().map(|n| *n as u64).collect();
+
+        zip.start_file("data.npy", options)?;
+        write_npy(&mut zip, &self.data)?;
+        zip.start_file("populations.npy", options)?;
+        write_npy_strings(&mut zip, &self.populations)?;
+        zip.start_file("sample_sizes.npy", options)?;
+        write_npy(
+            &mut zip,
+            &ArrayD::from_shape_vec(IxDyn(&[sample_sizes.len()]), sample_sizes).expect("1-d"),
+        )?;
+        zip.start_file("kmax.npy", options)?;
+        write_npy(
+            &mut zip,
+            &ArrayD::from_shape_vec(IxDyn(&[self.kmax.len()]), self.kmax.clone()).expect("1-d"),
+        )?;
+
+        zip.finish()?.flush()
+    }
+
+    /// Write `.npz` file at `path`
+    pub fn to_path<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write(BufWriter::new(File::create(path)?))
+    }
+
+    /// Read `.npz` archive written by `write` or `numpy.savez`; metadata entries are optional
+    pub fn read<R: Read + Seek>(reader: R) -> Result<Self, BsfsError> {
+        let mut zip = ZipArchive::new(reader).map_err(npz_error)?;
+        let data = read_npy(zip.by_name("data.npy").map_err(npz_error)?)?;
+        let populations = match zip.by_name("populations.npy") {
+            Ok(entry) => read_npy_strings(entry)?,
+            Err(_) => vec![],
+        };
+        let sample_sizes = match zip.by_name("sample_sizes.npy") {
+            Ok(entry) => read_npy(entry)?.iter().map(|n| *n as usize).collect(),
+            Err(_) => data
+                .shape()
+                .iter()
+                .map(|dim| dim.saturating_sub(1))
+                .collect(),
+        };
+        let kmax = match zip.by_name("kmax.npy") {
+            Ok(entry) => read_npy(entry)?.iter().copied().collect(),
+            Err(_) => vec![],
+        };
+
+        Ok(NpzSpectrum {
+            data,
+            populations,
+            sample_sizes,
+            kmax,
+        })
+    }
+
+    /// Read `.npz` file at `path`
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, BsfsError> {
+        NpzSpectrum::read(BufReader::new(File::open(path)?))
+    }
+}
+
+/// Parsed `.npy` header dictionary
+struct NpyHeader {
+    descr: String,
+    fortran_order: bool,
+    shape: Vec<usize>,
+}
+
+impl NpyHeader {
+    fn into_array(self, data: Vec<u64>) -> Result<ArrayD<u64>, BsfsError> {
+        let shape = IxDyn(&self.shape);
+        let array = if self.fortran_order {
+            ArrayD::from_shape_vec(shape.f(), data)
+        } else {
+            ArrayD::from_shape_vec(shape, data)
+        };
+
+        array.map_err(|_| BsfsError::parse(FORMAT, 0, "number of values does not match shape"))
+    }
+}
+
+/// Version 1.0 header, padded so that the data starts on a 64-byte boundary
+fn write_header<W: Write>(out: &mut W, descr: &str, shape: &[usize]) -> io::Result<()> {
+    let shape = match shape {
+        [dim] => format!("({},)", dim),
+        _ => format!(
+            "({})",
+            shape
+                .iter()
+                .map(usize::to_string)
+                .collect::<Vec<String>>()
+                .join(", ")
+        ),
+    };
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+        descr, shape
+    );
+    let unpadded = MAGIC.len() + 4 + header.len() + 1;
+    header.push_str(&" ".repeat((64 - unpadded % 64) % 64));
+    header.push('\n');
+
+    out.write_all(MAGIC)?;
+    out.write_all(&[1, 0])?;
+    out.write_all(&(header.len() as u16).to_le_bytes())?;
+    out.write_all(header.as_bytes())
+}
+
+fn read_header<R: Read>(reader: &mut R) -> Result<NpyHeader, BsfsError> {
+    let mut preamble = [0u8; 8];
+    reader.read_exact(&mut preamble)?;
+    if &preamble[..6] != MAGIC {
+        return Err(BsfsError::parse(FORMAT, 0, "missing magic string"));
+    }
+
+    let header_len = match preamble[6] {
+        1 => {
+            let mut len = [0u8; 2];
+            reader.read_exact(&mut len)?;
+            u16::from_le_bytes(len) as usize
+        }
+        2 | 3 => {
+            let mut len = [0u8; 4];
+            reader.read_exact(&mut len)?;
+            u32::from_le_bytes(len) as usize
+        }
+        version => {
+            return Err(BsfsError::parse(
+                FORMAT,
+                0,
+                format!("unsupported version {}", version),
+            ))
+        }
+    };
+    let mut header = vec![0u8; header_len];
+    reader.read_exact(&mut header)?;
+    let header = String::from_utf8(header)
+        .map_err(|_| BsfsError::parse(FORMAT, 0, "header is not UTF-8"))?;
+
+    let descr = dict_value(&header, "descr")?
+        .split(['\'', '"'])
+        .nth(1)
+        .ok_or_else(|| BsfsError::parse(FORMAT, 0, "invalid descr"))?
+        .to_string();
+    let fortran_order = dict_value(&header, "fortran_order")?.starts_with("True");
+    let shape = dict_value(&header, "shape")?;
+    let shape: Vec<usize> = shape[1..shape.find(')').unwrap_or(shape.len())]
+        .split(',')
+        .map(str::trim)
+        .filter(|dim| !dim.is_empty())
+        .map(|dim| dim.parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| BsfsError::parse(FORMAT, 0, format!("invalid shape '{}'", shape)))?;
+
+    Ok(NpyHeader {
+        descr,
+        fortran_order,
+        shape,
+    })
+}
+
+/// Text following `'key':` in the header dictionary
+fn dict_value<'a>(header: &'a str, key: &str) -> Result<&'a str, BsfsError> {
+    let pattern = format!("'{}':", key);
+    header
+        .find(&pattern)
+        .map(|start| header[start + pattern.len()..].trim_start())
+        .ok_or_else(|| BsfsError::parse(FORMAT, 0, format!("header has no '{}'", key)))
+}
+
+fn npz_error(e: ZipError) -> BsfsError {
+    match e {
+        ZipError::Io(e) => BsfsError::Io(e),
+        e => BsfsError::parse(".npz", 0, e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::{fixture, rstest};
+    use std::io::Cursor;
+
+    #[fixture]
+    fn sfs() -> ArrayD<u64> {
+        ArrayD::from_shape_vec(IxDyn(&[2, 3]), vec![7, 1, 0, 2, 0, 3]).unwrap()
+    }
+
+    #[rstest]
+    fn test_write_npy(sfs: ArrayD<u64>) {
+        let mut out: Vec<u8> = vec![];
+        write_npy(&mut out, &sfs).unwrap();
+        let header = String::from_utf8_lossy(&out[10..128]);
+
+        assert_eq!(&out[..8], b"\x93NUMPY\x01\x00");
+        assert_eq!(out.len(), 128 + 6 * 8);
+        assert!(header.starts_with("{'descr': '<u8', 'fortran_order': False, 'shape': (2, 3), }"));
+        assert!(header.ends_with('\n'));
+        assert_eq!(&out[128..136], &7u64.to_le_bytes());
+    }
+
+    #[rstest]
+    fn test_npy_round_trip(sfs: ArrayD<u64>) {
+        let mut out: Vec<u8> = vec![];
+        write_npy(&mut out, &sfs).unwrap();
+
+        assert_eq!(read_npy(Cursor::new(out)).unwrap(), sfs)
+    }
+
+    #[rstest]
+    fn test_read_npy_fortran_order() {
+        let header = "{'descr': '<i8', 'fortran_order': True, 'shape': (2, 2), }";
+        let mut bytes: Vec<u8> = b"\x93NUMPY\x01\x00".to_vec();
+        bytes.extend((header.len() as u16).to_le_bytes());
+        bytes.extend(header.as_bytes());
+        for value in [1i64, 2, 3, 4] {
+            bytes.extend(value.to_le_bytes());
+        }
+
+        assert_eq!(
+            read_npy(Cursor::new(bytes)).unwrap(),
+            ndarray::array![[1, 3], [2, 4]].into_dyn()
+        )
+    }
+
+    #[rstest]
+    #[case("(2, 3)")]
+    #[case("(4294967296, 4294967296)")]
+    fn test_read_npy_short_data(#[case] shape: &str) {
+        let header = format!(
+            "{{'descr': '<u8', 'fortran_order': False, 'shape': {}, }}",
+            shape
+        );
+        let mut bytes: Vec<u8> = b"\x93NUMPY\x01\x00".to_vec();
+        bytes.extend((header.len() as u16).to_le_bytes());
+        bytes.extend(header.as_bytes());
+        bytes.extend([0u8; 40]);
+
+        assert!(matches!(
+            read_npy(Cursor::new(bytes)),
+            Err(BsfsError::Parse { .. })
+        ))
+    }
+
+    #[rstest]
+    fn test_npy_strings_round_trip() {
+        let strings = vec!["popA".to_string(), "B".to_string()];
+        let mut out: Vec<u8> = vec![];
+        write_npy_strings(&mut out, &strings).unwrap();
+
+        assert!(String::from_utf8_lossy(&out).contains("'descr': '<U4'"));
+        assert_eq!(read_npy_strings(Cursor::new(out)).unwrap(), strings)
+    }
+
+    #[rstest]
+    fn test_npz_round_trip() {
+        let mut tally = BlockTally::new(vec![2, 1]);
+        tally.add_count(&[3, 0], 4).unwrap();
+        tally.add(&[1, 1]).unwrap();
+        let spectrum = NpzSpectrum::from_tally(
+            &tally,
+            vec!["popA".to_string(), "popB".to_string()],
+            vec![2, 2],
+        );
+        let mut out = Cursor::new(vec![]);
+        spectrum.write(&mut out).unwrap();
+        let read = NpzSpectrum::read(out).unwrap();
+
+        assert_eq!(read, spectrum);
+        assert_eq!(read.to_tally().unwrap(), tally);
+    }
+
+    #[rstest]
+    fn test_npz_sfs(sfs: ArrayD<u64>) {
+        let spectrum = NpzSpectrum::from_sfs(sfs, vec![]);
+
+        assert_eq!(spectrum.sample_sizes, vec![1, 2]);
+        assert!(spectrum.to_tally().is_none());
+    }
+}
@@ -0,0 +1,256 @@
+//! Bit-packed sites: one derived bit and one missing bit per haplotype
+//!
+//! Derived-allele and called counts per population are popcounts of the site words against
+//! precomputed population bitmasks, a fast path for `site_to_entry` and `called_per_pop` on large
+//! cohorts. As there, any alternate allele counts as derived.
+
+use crate::{error::BsfsError, genotype::Genotype, sample_map::SampleMap, Site};
+use ndarray::{ArrayD, IxDyn};
+
+const WORD_BITS: usize = u64::BITS as usize;
+
+/// Biallelic site packed into 64-haplotype words, in flattened haplotype order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackedSite {
+    n_haplotypes: usize,
+    derived: Vec<u64>,
+    missing: Vec<u64>,
+}
+
+impl PackedSite {
+    /// Pack a flattened site (as from `flatten_site`)
+    pub fn from_haplotypes(site: &[u32]) -> Self {
+        PackedSite::pack(site.iter().copied())
+    }
+
+    /// Pack per-sample calls without flattening them first
+    pub fn from_calls(calls: &[Vec<u32>]) -> Self {
+        PackedSite::pack(calls.iter().flatten().copied())
+    }
+
+    fn pack(haplotypes: impl Iterator<Item = u32>) -> Self {
+        let mut packed = PackedSite {
+            n_haplotypes: 0,
+            derived: vec![],
+            missing: vec![],
+        };
+
+        for (hap, allele) in haplotypes.enumerate() {
+            if hap % WORD_BITS == 0 {
+                packed.derived.push(0);
+                packed.missing.push(0);
+            }
+            let (word, bit) = (hap / WORD_BITS, 1 << (hap % WORD_BITS));
+            match Genotype::from(allele) {
+                Genotype::Alt(_) => packed.derived[word] |= bit,
+                Genotype::Missing => packed.missing[word] |= bit,
+                Genotype::Ref => {}
+            }
+            packed.n_haplotypes += 1;
+        }
+
+        packed
+    }
+
+    pub fn n_haplotypes(&self) -> usize {
+        self.n_haplotypes
+    }
+
+    /// Whether any call is missing
+    pub fn has_missing(&self) -> bool {
+        self.missing.iter().any(|word| *word != 0)
+    }
+}
+
+impl From<&Site> for PackedSite {
+    fn from(site: &Site) -> Self {
+        PackedSite::from_calls(&site.calls)
+    }
+}
+
+/// Haplotype bitmask of each population of a `SampleMap`, in population order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PopulationMasks {
+    n_haplotypes: usize,
+    masks: Vec<Vec<u64>>,
+}
+
+impl PopulationMasks {
+    pub fn new(sample_map: &SampleMap) -> Self {
+        let n_words = sample_map.n_haplotypes().div_ceil(WORD_BITS);
+        let mut masks = vec![vec![0; n_words]; sample_map.n_populations()];
+
+        for hap in 0..sample_map.n_haplotypes() {
+            if let Some(pop_idx) = sample_map.population_index(hap) {
+                masks[pop_idx][hap / WORD_BITS] |= 1 << (hap % WORD_BITS);
+            }
+        }
+
+        PopulationMasks {
+            n_haplotypes: sample_map.n_haplotypes(),
+            masks,
+        }
+    }
+
+    /// Derived haplotypes per population; packed equivalent of `site_to_entry`
+    pub fn site_to_entry(&self, site: &PackedSite) -> Vec<usize> {
+        self.count(site, |derived, _| derived)
+    }
+
+    /// Called haplotypes per population; packed equivalent of `called_per_pop`
+    pub fn called_per_pop(&self, site: &PackedSite) -> Vec<usize> {
+        self.count(site, |_, missing| !missing)
+    }
+
+    /// Error unless `site` has one call per haplotype; `site` names it in the error
+    ///
+    /// Packed sites keep no sample boundaries, so only the total number of calls is checked.
+    pub fn check_ploidy(
+        &self,
+        packed: &PackedSite,
+        site: impl FnOnce() -> String,
+    ) -> Result<(), BsfsError> {
+        if packed.n_haplotypes == self.n_haplotypes {
+            return Ok(());
+        }
+
+        Err(BsfsError::RaggedPloidy {
+            site: site(),
+            expected: self.n_haplotypes,
+            found: packed.n_haplotypes,
+        })
+    }
+
+    /// Popcount of `bits(derived, missing)` within each population mask
+    fn count(&self, site: &PackedSite, bits: impl Fn(u64, u64) -> u64) -> Vec<usize> {
+        self.masks
+            .iter()
+            .map(|mask| {
+                mask.iter()
+                    .zip(site.derived.iter().zip(&site.missing))
+                    .map(|(mask, (derived, missing))| {
+                        (mask & bits(*derived, *missing)).count_ones() as usize
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+}
+
+/// Joint SFS array over packed sites; packed equivalent of `bsfs_matrix`
+pub fn packed_bsfs_matrix(
+    sites: impl IntoIterator<Item = PackedSite>,
+    sample_map: &SampleMap,
+) -> Result<ArrayD<u64>, BsfsError> {
+    sample_map.validate()?;
+    let masks = PopulationMasks::new(sample_map);
+    let mut sfs: ArrayD<u64> = ArrayD::zeros(IxDyn(&sample_map.sfs_shape()));
+
+    for (idx, site) in sites.into_iter().enumerate() {
+        masks.check_ploidy(&site, || format!("site {}", idx + 1))?;
+        sfs[IxDyn(&masks.site_to_entry(&site))] += 1;
+    }
+
+    Ok(sfs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bsfs_matrix, called_per_pop, site_to_entry, vcf::MISSING};
+    use rstest::{fixture, rstest};
+
+    /// 150 haplotypes over three words; every fifth unmapped, the rest split over three populations
+    #[fixture]
+    fn sample_map() -> SampleMap {
+        let populations = ["popA", "popB", "popC"];
+        let haplotypes: Vec<Option<&str>> = (0..150)
+            .map(|hap| (hap % 5 != 4).then_some(populations[hap % 3]))
+            .collect();
+
+        SampleMap::from_haplotypes(&haplotypes)
+    }
+
+    /// The same 150 haplotypes, all mapped
+    #[fixture]
+    fn mapped_sample_map() -> SampleMap {
+        let populations = ["popA", "popB", "popC"];
+        let haplotypes: Vec<Option<&str>> =
+            (0..150).map(|hap| Some(populations[hap % 3])).collect();
+
+        SampleMap::from_haplotypes(&haplotypes)
+    }
+
+    #[fixture]
+    fn calls() -> Vec<Vec<Vec<u32>>> {
+        (0..20u32)
+            .map(|idx| {
+                (0..75u32)
+                    .map(|sample| {
+                        let allele = |hap: u32| match (idx + hap) % 7 {
+                            0 => MISSING,
+                            1 | 4 => 1,
+                            5 => 2,
+                            _ => 0,
+                        };
+                        vec![allele(2 * sample), allele(2 * sample + 1)]
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[rstest]
+    fn test_pack() {
+        let packed = PackedSite::from_calls(&[vec![0, 1], vec![MISSING, 2]]);
+
+        assert_eq!(packed.n_haplotypes(), 4);
+        assert_eq!(packed.derived, vec![0b1010]);
+        assert_eq!(packed.missing, vec![0b0100]);
+        assert!(packed.has_missing());
+        assert_eq!(packed, PackedSite::from_haplotypes(&[0, 1, MISSING, 2]));
+    }
+
+    #[rstest]
+    fn test_counts_match_unpacked(calls: Vec<Vec<Vec<u32>>>, sample_map: SampleMap) {
+        let masks = PopulationMasks::new(&sample_map);
+
+        for site in calls {
+            let packed = PackedSite::from_calls(&site);
+            let flat: Vec<u32> = site.concat();
+
+            assert_eq!(
+                masks.site_to_entry(&packed),
+                site_to_entry(&flat, &sample_map)
+            );
+            assert_eq!(
+                masks.called_per_pop(&packed),
+                called_per_pop(&flat, &sample_map)
+            );
+        }
+    }
+
+    #[rstest]
+    fn test_packed_bsfs_matrix(calls: Vec<Vec<Vec<u32>>>, mapped_sample_map: SampleMap) {
+        let packed: Vec<PackedSite> = calls
+            .iter()
+            .map(|site| PackedSite::from_calls(site))
+            .collect();
+
+        assert_eq!(
+            packed_bsfs_matrix(packed, &mapped_sample_map).unwrap(),
+            bsfs_matrix(calls, &mapped_sample_map).unwrap()
+        );
+    }
+
+    #[rstest]
+    fn test_packed_bsfs_matrix_ragged_ploidy(mapped_sample_map: SampleMap) {
+        let err = packed_bsfs_matrix([PackedSite::from_haplotypes(&[0; 149])], &mapped_sample_map)
+            .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "site 1: expected 150 haplotype calls, found 149"
+        );
+    }
+}
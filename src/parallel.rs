@@ -0,0 +1,206 @@
+//! Parallel versions of the SFS and bSFS computations
+//!
+//! Work is spread over the current rayon thread pool: the global pool by default, or one set up
+//! with `rayon::ThreadPoolBuilder` (see `with_threads`). Partial results are merged with an
+//! associative reduce, so output does not depend on the number of threads.
+
+use crate::{
+    blocks::{block_configuration, block_entries, Block},
+    check_ploidy,
+    error::BsfsError,
+    flatten_site,
+    fold::{folded_configuration, folded_type_index},
+    four_type::{check_four_type, four_type_configuration},
+    sample_map::SampleMap,
+    site_to_entry,
+    tally::BlockTally,
+};
+use ndarray::{ArrayD, IxDyn};
+use rayon::prelude::*;
+
+/// Run `f` on a thread pool with `threads` threads; 0 uses one thread per core
+pub fn with_threads<T, F>(threads: usize, f: F) -> Result<T, BsfsError>
+where
+    T: Send,
+    F: FnOnce() -> T + Send,
+{
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .map_err(BsfsError::ThreadPool)?;
+
+    Ok(pool.install(f))
+}
+
+/// Parallel `bsfs_matrix`: joint SFS array over all sites
+///
+/// Sites are split into one chunk per thread, each counted into its own dense array; the arrays
+/// are added up at the end.
+pub fn par_bsfs_matrix(
+    calls: Vec<Vec<Vec<u32>>>,
+    sample_map: &SampleMap,
+) -> Result<ArrayD<u64>, BsfsError> {
+    sample_map.validate()?;
+    let shape = sample_map.sfs_shape();
+    let empty = || ArrayD::<u64>::zeros(IxDyn(&shape));
+    // The array can be large, so allocate one per thread rather than one per rayon split
+    let chunk = calls.len().div_ceil(rayon::current_num_threads()).max(1);
+
+    calls
+        .into_par_iter()
+        .enumerate()
+        .with_min_len(chunk)
+        .try_fold(empty, |mut matrix, (idx, site)| {
+            check_ploidy(&site, sample_map, || format!("site {}", idx + 1))?;
+            matrix[IxDyn(&site_to_entry(&flatten_site(site), sample_map))] += 1;
+            Ok(matrix)
+        })
+        .try_reduce_with(|mut a, b| {
+            a += &b;
+            Ok(a)
+        })
+        .unwrap_or_else(|| Ok(empty()))
+}
+
+/// Parallel `blocks::tally_blocks`
+pub fn par_tally_blocks(
+    blocks: Vec<Block>,
+    sample_map: &SampleMap,
+    kmax: Vec<u64>,
+) -> Result<BlockTally, BsfsError> {
+    let shape = sample_map.sfs_shape();
+
+    par_tally(blocks, sample_map, kmax, |entries| {
+        block_configuration(entries, &shape)
+    })
+}
+
+/// Parallel `fold::tally_blocks_folded`
+pub fn par_tally_blocks_folded(
+    blocks: Vec<Block>,
+    sample_map: &SampleMap,
+    kmax: Vec<u64>,
+) -> Result<BlockTally, BsfsError> {
+    let shape = sample_map.sfs_shape();
+    let types = folded_type_index(&shape);
+
+    par_tally(blocks, sample_map, kmax, |entries| {
+        folded_configuration(entries, &shape, &types)
+    })
+}
+
+/// Parallel `four_type::tally_blocks_four_type`
+pub fn par_tally_blocks_four_type(
+    blocks: Vec<Block>,
+    sample_map: &SampleMap,
+    kmax: Vec<u64>,
+) -> Result<BlockTally, BsfsError> {
+    check_four_type(sample_map)?;
+
+    par_tally(blocks, sample_map, kmax, four_type_configuration)
+}
+
+/// Tally blocks in parallel, one configuration per block, merging per-thread tallies
+fn par_tally<F>(
+    blocks: Vec<Block>,
+    sample_map: &SampleMap,
+    kmax: Vec<u64>,
+    configuration: F,
+) -> Result<BlockTally, BsfsError>
+where
+    F: Fn(&[Vec<usize>]) -> Vec<u64> + Sync,
+{
+    sample_map.validate()?;
+    let empty = || BlockTally::new(kmax.clone());
+
+    blocks
+        .into_par_iter()
+        .try_fold(empty, |mut tally, block| {
+            let entries = block_entries(block, sample_map)?;
+            tally.add(&configuration(&entries))?;
+            Ok(tally)
+        })
+        .try_reduce(empty, |mut a, b| {
+            a.merge(b)?;
+            Ok(a)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::sample_map;
+    use crate::{
+        blocks::{make_blocks, tally_blocks, BlockLength},
+        bsfs_matrix,
+        fold::tally_blocks_folded,
+        four_type::tally_blocks_four_type,
+        Site,
+    };
+    use rstest::{fixture, rstest};
+
+    #[fixture]
+    fn sites() -> Vec<Site> {
+        (0..200)
+            .map(|idx: u64| Site {
+                chrom: format!("chr{}", idx / 100),
+                pos: idx * 7 + 1,
+                calls: vec![
+                    vec![(idx % 2) as u32, idx.is_multiple_of(3) as u32],
+                    vec![idx.is_multiple_of(5) as u32, (idx % 7 < 2) as u32],
+                ],
+            })
+            .collect()
+    }
+
+    #[rstest]
+    fn test_par_bsfs_matrix(sites: Vec<Site>, sample_map: SampleMap) {
+        let calls: Vec<Vec<Vec<u32>>> = sites.into_iter().map(|site| site.calls).collect();
+
+        assert_eq!(
+            with_threads(4, || par_bsfs_matrix(calls.clone(), &sample_map))
+                .unwrap()
+                .unwrap(),
+            bsfs_matrix(calls, &sample_map).unwrap()
+        );
+    }
+
+    #[rstest]
+    fn test_par_tally_blocks(sites: Vec<Site>, sample_map: SampleMap) {
+        let blocks = make_blocks(sites, BlockLength::Sites(3)).unwrap();
+        let kmax = vec![1; 7];
+        assert_eq!(blocks.len(), 66);
+
+        assert_eq!(
+            with_threads(4, || par_tally_blocks(
+                blocks.clone(),
+                &sample_map,
+                kmax.clone()
+            ))
+            .unwrap()
+            .unwrap(),
+            tally_blocks(blocks.clone(), &sample_map, kmax.clone()).unwrap()
+        );
+        assert_eq!(
+            par_tally_blocks_folded(blocks.clone(), &sample_map, vec![1; 4]).unwrap(),
+            tally_blocks_folded(blocks.clone(), &sample_map, vec![1; 4]).unwrap()
+        );
+        assert_eq!(
+            par_tally_blocks_four_type(blocks.clone(), &sample_map, vec![2; 4]).unwrap(),
+            tally_blocks_four_type(blocks, &sample_map, vec![2; 4]).unwrap()
+        );
+    }
+
+    #[rstest]
+    fn test_par_tally_blocks_ragged_ploidy(mut sites: Vec<Site>, sample_map: SampleMap) {
+        sites[100].calls[1].pop();
+        let err = par_tally_blocks(
+            make_blocks(sites, BlockLength::Sites(3)).unwrap(),
+            &sample_map,
+            vec![1; 7],
+        )
+        .unwrap_err();
+
+        assert!(matches!(err, BsfsError::RaggedPloidy { .. }));
+    }
+}
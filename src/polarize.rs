@@ -0,0 +1,266 @@
+use crate::{error::BsfsError, genotype::Genotype, vcf::VcfRecord, Site};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+/// Ancestral sequence per chromosome, read from FASTA
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct AncestralFasta {
+    sequences: HashMap<String, Vec<u8>>,
+}
+
+impl AncestralFasta {
+    /// Parse FASTA records; sequence names are the first word of each header line
+    pub fn from_fasta<R: BufRead>(reader: R) -> Result<Self, BsfsError> {
+        let mut fasta = AncestralFasta::default();
+        let mut current: Option<&mut Vec<u8>> = None;
+
+        for (idx, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim_end();
+
+            if let Some(header) = line.strip_prefix('>') {
+                let name = header.split_whitespace().next().unwrap_or_default();
+                current = Some(fasta.sequences.entry(name.to_string()).or_default());
+            } else if !line.is_empty() {
+                match current.as_mut() {
+                    Some(sequence) => sequence.extend_from_slice(line.as_bytes()),
+                    None => {
+                        return Err(BsfsError::parse(
+                            "FASTA",
+                            idx + 1,
+                            "sequence before first header",
+                        ))
+                    }
+                }
+            }
+        }
+
+        Ok(fasta)
+    }
+
+    /// Load FASTA at `path`
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, BsfsError> {
+        AncestralFasta::from_fasta(BufReader::new(File::open(path)?))
+    }
+
+    /// Ancestral base at 1-based position `pos`, upper-cased
+    pub fn base(&self, chrom: &str, pos: u64) -> Option<u8> {
+        let idx = usize::try_from(pos).ok()?.checked_sub(1)?;
+        self.sequences
+            .get(chrom)?
+            .get(idx)
+            .map(u8::to_ascii_uppercase)
+    }
+}
+
+/// Where the ancestral allele is taken from
+#[derive(Debug, Clone, PartialEq)]
+pub enum AncestralSource {
+    /// Ancestral sequence FASTA
+    Fasta(AncestralFasta),
+    /// Outgroup samples in the callset (VCF column indices); all called alleles must agree
+    Outgroup(Vec<usize>),
+    /// `AA` INFO tag; anything after a `|` is ignored
+    InfoAa,
+}
+
+/// Polarization run statistics
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PolarizationStats {
+    /// Sites where REF is ancestral
+    pub ref_ancestral: u64,
+    /// Sites flipped because an ALT allele is ancestral
+    pub flipped: u64,
+    /// Sites without a usable ancestral allele
+    pub unpolarized: u64,
+}
+
+/// Sites after polarization
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PolarizationOutcome {
+    /// Polarized sites; allele 0 is ancestral
+    pub sites: Vec<Site>,
+    /// Sites that could not be polarized, left as in the VCF
+    pub unpolarized: Vec<Site>,
+    pub stats: PolarizationStats,
+}
+
+/// Index of the ancestral allele among REF (0) and ALTs; None if unknown or not among them
+pub fn ancestral_allele(record: &VcfRecord, source: &AncestralSource) -> Option<u32> {
+    match source {
+        AncestralSource::Fasta(fasta) => {
+            let base = fasta.base(&record.chrom, record.pos)?;
+            allele_index(record, std::str::from_utf8(&[base]).ok()?)
+        }
+        AncestralSource::InfoAa => {
+            let aa = record.info_field("AA")?.split('|').next()?;
+            allele_index(record, aa)
+        }
+        AncestralSource::Outgroup(samples) => {
+            let mut alleles = samples
+                .iter()
+                .flat_map(|idx| record.calls.get(*idx).into_iter().flatten())
+                .filter(|allele| Genotype::from(**allele).is_called());
+            let first = *alleles.next()?;
+
+            alleles.all(|allele| *allele == first).then_some(first)
+        }
+    }
+}
+
+/// Swap allele indices so that `ancestral` becomes 0
+pub fn polarize_calls(calls: &mut [Vec<u32>], ancestral: u32) {
+    for allele in calls.iter_mut().flatten() {
+        if *allele == ancestral {
+            *allele = 0;
+        } else if *allele == 0 {
+            *allele = ancestral;
+        }
+    }
+}
+
+/// Polarize records against `source`; outgroup samples are removed from the resulting calls
+///
+/// With `AncestralSource::Outgroup`, the sample map must be built without the outgroup samples.
+pub fn polarize_records(records: Vec<VcfRecord>, source: &AncestralSource) -> PolarizationOutcome {
+    let mut outcome = PolarizationOutcome::default();
+
+    for record in records {
+        let ancestral = ancestral_allele(&record, source);
+        let mut site = Site::from(record);
+        if let AncestralSource::Outgroup(samples) = source {
+            site.calls = site
+                .calls
+                .into_iter()
+                .enumerate()
+                .filter(|(idx, _)| !samples.contains(idx))
+                .map(|(_, calls)| calls)
+                .collect();
+        }
+
+        match ancestral {
+            Some(0) => {
+                outcome.stats.ref_ancestral += 1;
+                outcome.sites.push(site);
+            }
+            Some(ancestral) => {
+                outcome.stats.flipped += 1;
+                polarize_calls(&mut site.calls, ancestral);
+                outcome.sites.push(site);
+            }
+            None => {
+                outcome.stats.unpolarized += 1;
+                outcome.unpolarized.push(site);
+            }
+        }
+    }
+
+    outcome
+}
+
+fn allele_index(record: &VcfRecord, allele: &str) -> Option<u32> {
+    std::iter::once(&record.ref_allele)
+        .chain(&record.alt_alleles)
+        .position(|candidate| candidate.eq_ignore_ascii_case(allele))
+        .map(|idx| idx as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vcf::MISSING;
+    use rstest::{fixture, rstest};
+    use std::io::Cursor;
+
+    fn record(pos: u64, info: &str, calls: Vec<Vec<u32>>) -> VcfRecord {
+        VcfRecord {
+            chrom: "chr1".to_string(),
+            pos,
+            ref_allele: "A".to_string(),
+            alt_alleles: vec!["T".to_string()],
+            qual: None,
+            filter: vec![],
+            info: info.to_string(),
+            sample_format: vec![Default::default(); calls.len()],
+            calls,
+        }
+    }
+
+    #[fixture]
+    fn records() -> Vec<VcfRecord> {
+        vec![
+            record(1, "AA=A", vec![vec![0, 1], vec![0, 0]]),
+            record(2, "AA=t|||", vec![vec![0, 1], vec![1, 1]]),
+            record(3, "AA=G", vec![vec![0, 1], vec![MISSING, 1]]),
+            record(4, ".", vec![vec![1, 1], vec![0, 0]]),
+        ]
+    }
+
+    #[rstest]
+    fn test_ancestral_fasta() {
+        let fasta =
+            AncestralFasta::from_fasta(Cursor::new(">chr1 anc\nAc\ngN\n>chr2\nT\n")).unwrap();
+
+        assert_eq!(fasta.base("chr1", 2), Some(b'C'));
+        assert_eq!(fasta.base("chr1", 3), Some(b'G'));
+        assert_eq!(fasta.base("chr1", 5), None);
+        assert_eq!(fasta.base("chr2", 0), None);
+    }
+
+    #[rstest]
+    fn test_ancestral_allele(records: Vec<VcfRecord>) {
+        let fasta = AncestralFasta::from_fasta(Cursor::new(">chr1\nATCN\n")).unwrap();
+        let fasta = AncestralSource::Fasta(fasta);
+        let outgroup = AncestralSource::Outgroup(vec![1]);
+
+        assert_eq!(ancestral_allele(&records[1], &fasta), Some(1));
+        assert_eq!(ancestral_allele(&records[3], &fasta), None);
+        assert_eq!(
+            ancestral_allele(&records[1], &AncestralSource::InfoAa),
+            Some(1)
+        );
+        assert_eq!(
+            ancestral_allele(&records[2], &AncestralSource::InfoAa),
+            None
+        );
+        assert_eq!(ancestral_allele(&records[2], &outgroup), Some(1));
+        assert_eq!(ancestral_allele(&records[0], &outgroup), Some(0));
+    }
+
+    #[rstest]
+    fn test_polarize_calls() {
+        let mut calls = vec![vec![0, 1], vec![1, MISSING]];
+        polarize_calls(&mut calls, 1);
+
+        assert_eq!(calls, vec![vec![1, 0], vec![0, MISSING]])
+    }
+
+    #[rstest]
+    fn test_polarize_records(records: Vec<VcfRecord>) {
+        let outcome = polarize_records(records, &AncestralSource::InfoAa);
+
+        assert_eq!(outcome.sites.len(), 2);
+        assert_eq!(outcome.sites[1].calls, vec![vec![1, 0], vec![0, 0]]);
+        assert_eq!(outcome.unpolarized.len(), 2);
+        assert_eq!(
+            outcome.stats,
+            PolarizationStats {
+                ref_ancestral: 1,
+                flipped: 1,
+                unpolarized: 2
+            }
+        );
+    }
+
+    #[rstest]
+    fn test_polarize_records_outgroup(records: Vec<VcfRecord>) {
+        let outcome = polarize_records(records, &AncestralSource::Outgroup(vec![1]));
+
+        assert_eq!(outcome.stats.flipped, 2);
+        assert_eq!(outcome.sites[1].calls, vec![vec![1, 0]]);
+    }
+}
@@ -0,0 +1,229 @@
+use crate::{error::BsfsError, genotype::Genotype, sample_map::SampleMap};
+use itertools::Itertools;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+const FORMAT: &str = "popfile";
+
+/// Haplotype-level sample map built from a popfile, plus samples that could not be matched
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampleAssignment {
+    /// Haplotype index (as produced by `flatten_site`) to population; haplotypes of unassigned
+    /// samples are unmapped
+    pub sample_map: SampleMap,
+    /// Samples listed in the popfile but absent from the VCF header
+    pub unknown_samples: Vec<String>,
+    /// Samples in the VCF header without a population in the popfile
+    pub unassigned_samples: Vec<String>,
+}
+
+/// Parse `sample_name<TAB>population` lines; blank lines and `#` comments are skipped
+pub fn parse_popfile<R: BufRead>(reader: R) -> Result<Vec<(String, String)>, BsfsError> {
+    let mut assignments: Vec<(String, String)> = vec![];
+
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 2 || fields.iter().any(|field| field.is_empty()) {
+            return Err(BsfsError::parse(
+                FORMAT,
+                idx + 1,
+                "expected 'sample_name<TAB>population'",
+            ));
+        }
+        if assignments.iter().any(|(sample, _)| sample == fields[0]) {
+            return Err(BsfsError::parse(
+                FORMAT,
+                idx + 1,
+                format!("duplicate sample '{}'", fields[0]),
+            ));
+        }
+
+        assignments.push((fields[0].to_string(), fields[1].to_string()));
+    }
+
+    Ok(assignments)
+}
+
+/// Expand sample-level assignments into one sample map entry per haplotype
+///
+/// `samples` and `ploidies` are in VCF column order; ploidies are typically taken from
+/// `infer_ploidies`. Fails if a popfile population has no sample in `samples`.
+pub fn assign_haplotypes(
+    assignments: &[(String, String)],
+    samples: &[String],
+    ploidies: &[usize],
+) -> Result<SampleAssignment, BsfsError> {
+    let populations: HashMap<&str, &str> = assignments
+        .iter()
+        .map(|(sample, population)| (sample.as_str(), population.as_str()))
+        .collect();
+
+    let mut haplotypes: Vec<Option<&str>> = vec![];
+    let mut unassigned_samples: Vec<String> = vec![];
+
+    for (sample, ploidy) in samples.iter().zip(ploidies) {
+        let population = populations.get(sample.as_str()).copied();
+        if population.is_none() {
+            unassigned_samples.push(sample.to_string());
+        }
+        haplotypes.extend(std::iter::repeat_n(population, *ploidy));
+    }
+
+    let unknown_samples: Vec<String> = assignments
+        .iter()
+        .filter(|(sample, _)| !samples.contains(sample))
+        .map(|(sample, _)| sample.to_string())
+        .collect();
+
+    let empty_populations: Vec<String> = assignments
+        .iter()
+        .map(|(_, population)| population)
+        .unique()
+        .filter(|population| !haplotypes.contains(&Some(population.as_str())))
+        .cloned()
+        .collect();
+    if !empty_populations.is_empty() {
+        return Err(BsfsError::EmptyPopulations(empty_populations));
+    }
+
+    Ok(SampleAssignment {
+        sample_map: SampleMap::from_haplotypes(&haplotypes).with_ploidies(ploidies.to_vec())?,
+        unknown_samples,
+        unassigned_samples,
+    })
+}
+
+/// Ploidy of each of `n_samples` samples from the per-sample calls of successive sites
+///
+/// A sample's ploidy is the length of its first call with a called allele, as a missing `.` may
+/// be written haploid whatever the ploidy. Samples never called get the longest call seen, or 2
+/// if there are no sites.
+pub fn infer_ploidies<'a>(
+    sites: impl IntoIterator<Item = &'a [Vec<u32>]>,
+    n_samples: usize,
+) -> Vec<usize> {
+    let mut called: Vec<Option<usize>> = vec![None; n_samples];
+    let mut longest: Vec<Option<usize>> = vec![None; n_samples];
+
+    for calls in sites {
+        for ((call, called), longest) in calls.iter().zip(&mut called).zip(&mut longest) {
+            if called.is_none()
+                && call
+                    .iter()
+                    .any(|allele| Genotype::from(*allele).is_called())
+            {
+                *called = Some(call.len());
+            }
+            *longest = (*longest).max(Some(call.len()));
+        }
+        if called.iter().all(Option::is_some) {
+            break;
+        }
+    }
+
+    called
+        .into_iter()
+        .zip(longest)
+        .map(|(called, longest)| called.or(longest).unwrap_or(2))
+        .collect()
+}
+
+/// Load popfile at `path` and match it against VCF sample names and ploidies
+pub fn load_sample_map<P: AsRef<Path>>(
+    path: P,
+    samples: &[String],
+    ploidies: &[usize],
+) -> Result<SampleAssignment, BsfsError> {
+    let assignments = parse_popfile(BufReader::new(File::open(path)?))?;
+
+    assign_haplotypes(&assignments, samples, ploidies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vcf::MISSING;
+    use rstest::{fixture, rstest};
+    use std::io::Cursor;
+
+    #[fixture]
+    fn assignments() -> Vec<(String, String)> {
+        parse_popfile(Cursor::new(
+            "# sample\tpopulation\nind1\tpopA\nind2\tpopB\n\nind4\tpopB\n",
+        ))
+        .unwrap()
+    }
+
+    #[fixture]
+    fn samples() -> Vec<String> {
+        vec!["ind1".to_string(), "ind2".to_string(), "ind3".to_string()]
+    }
+
+    #[rstest]
+    fn test_parse_popfile(assignments: Vec<(String, String)>) {
+        let expected = vec![
+            ("ind1".to_string(), "popA".to_string()),
+            ("ind2".to_string(), "popB".to_string()),
+            ("ind4".to_string(), "popB".to_string()),
+        ];
+
+        assert_eq!(assignments, expected)
+    }
+
+    #[rstest]
+    fn test_parse_popfile_malformed() {
+        let err = parse_popfile(Cursor::new("ind1\tpopA\nind2 popB\n")).unwrap_err();
+
+        assert!(matches!(err, BsfsError::Parse { line: 2, .. }));
+        assert!(err.to_string().contains("line 2"));
+    }
+
+    #[rstest]
+    fn test_assign_haplotypes(assignments: Vec<(String, String)>, samples: Vec<String>) {
+        let assignment = assign_haplotypes(&assignments, &samples, &[2, 1, 2]).unwrap();
+        let expected =
+            SampleMap::from_haplotypes(&[Some("popA"), Some("popA"), Some("popB"), None, None])
+                .with_ploidies(vec![2, 1, 2])
+                .unwrap();
+
+        assert_eq!(assignment.sample_map, expected);
+        assert_eq!(assignment.sample_map.unmapped(), vec![3, 4]);
+        assert_eq!(assignment.unknown_samples, vec!["ind4"]);
+        assert_eq!(assignment.unassigned_samples, vec!["ind3"]);
+    }
+
+    #[rstest]
+    fn test_infer_ploidies() {
+        let sites: Vec<Vec<Vec<u32>>> = vec![
+            vec![vec![MISSING], vec![0], vec![MISSING]],
+            vec![vec![0, 1], vec![1], vec![MISSING, MISSING]],
+        ];
+
+        assert_eq!(
+            infer_ploidies(sites.iter().map(Vec::as_slice), 3),
+            vec![2, 1, 2]
+        );
+        assert_eq!(infer_ploidies([], 2), vec![2, 2]);
+    }
+
+    #[rstest]
+    fn test_assign_haplotypes_empty_population(samples: Vec<String>) {
+        let assignments = vec![
+            ("ind1".to_string(), "popA".to_string()),
+            ("ind9".to_string(), "popC".to_string()),
+        ];
+        let err = assign_haplotypes(&assignments, &samples, &[2, 2, 2]).unwrap_err();
+
+        assert!(matches!(err, BsfsError::EmptyPopulations(ref pops) if pops == &["popC"]));
+    }
+}
@@ -0,0 +1,336 @@
+use crate::{
+    called_per_pop, check_ploidy, error::BsfsError, flatten_site, sample_map::SampleMap,
+    site_to_entry,
+};
+use ndarray::{ArrayD, Dimension, IxDyn};
+
+/// Derived and called haplotype counts per population at a single site, in sorted population order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SiteCounts {
+    pub derived: Vec<usize>,
+    pub called: Vec<usize>,
+}
+
+/// Per-site derived and called counts from calls, via `site_to_entry` and `called_per_pop`
+///
+/// Fails if a site does not have one call per haplotype of `sample_map`.
+pub fn site_counts(
+    calls: Vec<Vec<Vec<u32>>>,
+    sample_map: &SampleMap,
+) -> Result<Vec<SiteCounts>, BsfsError> {
+    calls
+        .into_iter()
+        .enumerate()
+        .map(|(idx, site)| {
+            check_ploidy(&site, sample_map, || format!("site {}", idx + 1))?;
+            let site = flatten_site(site);
+
+            Ok(SiteCounts {
+                called: called_per_pop(&site, sample_map),
+                derived: site_to_entry(&site, sample_map),
+            })
+        })
+        .collect()
+}
+
+/// Probability of drawing `j` derived alleles in a subsample of `m` from `n` haplotypes with `k` derived
+///
+/// Zero when no such draw exists, including when `k` or `m` exceeds `n`.
+pub fn hypergeometric(n: usize, k: usize, m: usize, j: usize) -> f64 {
+    if k > n || m > n || j > k || j > m || m - j > n - k {
+        return 0.0;
+    }
+
+    (ln_binomial(k, j) + ln_binomial(n - k, m - j) - ln_binomial(n, m)).exp()
+}
+
+/// Hypergeometric distribution of derived counts 0..=m when projecting `k` of `n` down to `m`
+fn projection_weights(n: usize, k: usize, m: usize) -> Vec<f64> {
+    (0..=m).map(|j| hypergeometric(n, k, m, j)).collect()
+}
+
+/// Expected contribution of one site to the joint SFS of sample sizes `target`
+///
+/// None if fewer than `target` haplotypes are called in any population. Fails unless `target` has
+/// one sample size per population of the site.
+pub fn project_site(
+    counts: &SiteCounts,
+    target: &[usize],
+) -> Result<Option<ArrayD<f64>>, BsfsError> {
+    check_target(counts.called.len(), target)?;
+    check_counts(counts)?;
+    if counts.called.iter().zip(target).any(|(n, m)| n < m) {
+        return Ok(None);
+    }
+
+    let shape: Vec<usize> = target.iter().map(|m| m + 1).collect();
+    let weights: Vec<Vec<f64>> = counts
+        .called
+        .iter()
+        .zip(&counts.derived)
+        .zip(target)
+        .map(|((n, k), m)| projection_weights(*n, *k, *m))
+        .collect();
+
+    Ok(Some(ArrayD::from_shape_fn(IxDyn(&shape), |entry| {
+        entry
+            .slice()
+            .iter()
+            .zip(&weights)
+            .map(|(j, population_weights)| population_weights[*j])
+            .product()
+    })))
+}
+
+/// Project sites down to sample sizes `target`; sites with too few called haplotypes are skipped
+///
+/// Fails unless `target` has one sample size per population of every site.
+pub fn project_sites(sites: &[SiteCounts], target: &[usize]) -> Result<ArrayD<f64>, BsfsError> {
+    let shape: Vec<usize> = target.iter().map(|m| m + 1).collect();
+    let mut projected: ArrayD<f64> = ArrayD::zeros(IxDyn(&shape));
+
+    for site in sites {
+        if let Some(projected_site) = project_site(site, target)? {
+            projected += &projected_site;
+        }
+    }
+
+    Ok(projected)
+}
+
+/// Project joint SFS array (e.g. from `bsfs_matrix`) down to sample sizes `target`
+///
+/// Fails unless `target` has one sample size per axis, each at most that axis' sample size.
+pub fn project_matrix(matrix: &ArrayD<u64>, target: &[usize]) -> Result<ArrayD<f64>, BsfsError> {
+    let called: Vec<usize> = matrix.shape().iter().map(|dim| dim - 1).collect();
+    check_target(called.len(), target)?;
+    if let Some(population) = (0..target.len()).find(|idx| target[*idx] > called[*idx]) {
+        return Err(BsfsError::Mismatch(format!(
+            "projection target {} exceeds sample size {} of population {}",
+            target[population], called[population], population
+        )));
+    }
+
+    let shape: Vec<usize> = target.iter().map(|m| m + 1).collect();
+    let mut projected: ArrayD<f64> = ArrayD::zeros(IxDyn(&shape));
+
+    for (entry, count) in matrix.indexed_iter() {
+        if *count == 0 {
+            continue;
+        }
+        let counts = SiteCounts {
+            derived: entry.slice().to_vec(),
+            called: called.clone(),
+        };
+        if let Some(projected_site) = project_site(&counts, target)? {
+            projected.scaled_add(*count as f64, &projected_site);
+        }
+    }
+
+    Ok(projected)
+}
+
+/// Expected number of segregating sites in `population` for each projection size 1..=max called
+///
+/// Mirrors the easySFS preview: pick the size maximising segregating sites per population. Fails if
+/// a site has no population `population`.
+pub fn scan_projections(
+    sites: &[SiteCounts],
+    population: usize,
+) -> Result<Vec<(usize, f64)>, BsfsError> {
+    if let Some(site) = sites.iter().find(|site| population >= site.called.len()) {
+        return Err(BsfsError::Mismatch(format!(
+            "population index {} out of range for {} populations",
+            population,
+            site.called.len()
+        )));
+    }
+    for site in sites {
+        check_counts(site)?;
+    }
+
+    let max_called = sites
+        .iter()
+        .map(|site| site.called[population])
+        .max()
+        .unwrap_or(0);
+
+    Ok((1..=max_called)
+        .map(|m| {
+            let segregating: f64 = sites
+                .iter()
+                .filter(|site| site.called[population] >= m)
+                .map(|site| {
+                    let (n, k) = (site.called[population], site.derived[population]);
+                    1.0 - hypergeometric(n, k, m, 0) - hypergeometric(n, k, m, m)
+                })
+                .sum();
+
+            (m, segregating)
+        })
+        .collect())
+}
+
+/// Projection size maximising the expected number of segregating sites in `population`
+pub fn best_projection(
+    sites: &[SiteCounts],
+    population: usize,
+) -> Result<Option<usize>, BsfsError> {
+    Ok(scan_projections(sites, population)?
+        .into_iter()
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(m, _)| m))
+}
+
+/// Error unless each population has at most as many derived as called haplotypes
+fn check_counts(counts: &SiteCounts) -> Result<(), BsfsError> {
+    match counts
+        .derived
+        .iter()
+        .zip(&counts.called)
+        .find(|(k, n)| k > n)
+    {
+        Some((k, n)) => Err(BsfsError::InvalidParameter(format!(
+            "{} derived haplotypes out of {} called",
+            k, n
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Error unless `target` has one sample size per population
+fn check_target(n_populations: usize, target: &[usize]) -> Result<(), BsfsError> {
+    if target.len() == n_populations {
+        return Ok(());
+    }
+
+    Err(BsfsError::Mismatch(format!(
+        "projection target has {} populations, expected {}",
+        target.len(),
+        n_populations
+    )))
+}
+
+fn ln_binomial(n: usize, k: usize) -> f64 {
+    (1..=k.min(n - k))
+        .map(|i| ((n + 1 - i) as f64).ln() - (i as f64).ln())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vcf::MISSING;
+    use rstest::{fixture, rstest};
+    use std::collections::HashMap;
+
+    #[fixture]
+    fn sites() -> Vec<SiteCounts> {
+        vec![
+            SiteCounts {
+                derived: vec![1, 0],
+                called: vec![4, 2],
+            },
+            SiteCounts {
+                derived: vec![2, 1],
+                called: vec![3, 2],
+            },
+            SiteCounts {
+                derived: vec![1, 1],
+                called: vec![1, 2],
+            },
+        ]
+    }
+
+    #[rstest]
+    fn test_site_counts() {
+        let sample_map = SampleMap::new(&HashMap::from([
+            (0, "popA".to_string()),
+            (1, "popA".to_string()),
+            (2, "popB".to_string()),
+            (3, "popB".to_string()),
+        ]));
+        let counts = site_counts(vec![vec![vec![1, MISSING], vec![0, 1]]], &sample_map).unwrap();
+        let expected = vec![SiteCounts {
+            derived: vec![1, 1],
+            called: vec![1, 2],
+        }];
+
+        assert_eq!(counts, expected)
+    }
+
+    #[rstest]
+    fn test_hypergeometric() {
+        assert!((hypergeometric(4, 2, 2, 1) - 4.0 / 6.0).abs() < 1e-12);
+        assert!((hypergeometric(4, 2, 2, 0) - 1.0 / 6.0).abs() < 1e-12);
+        assert_eq!(hypergeometric(4, 1, 2, 2), 0.0);
+        assert!((hypergeometric(10, 10, 3, 3) - 1.0).abs() < 1e-12);
+        assert_eq!(hypergeometric(2, 3, 1, 1), 0.0);
+        assert_eq!(hypergeometric(2, 1, 3, 1), 0.0);
+    }
+
+    #[rstest]
+    fn test_project_site(sites: Vec<SiteCounts>) {
+        let projected = project_site(&sites[1], &[2, 2]).unwrap().unwrap();
+
+        assert!((projected[IxDyn(&[1, 1])] - 2.0 / 3.0).abs() < 1e-12);
+        assert!((projected[IxDyn(&[2, 1])] - 1.0 / 3.0).abs() < 1e-12);
+        assert!((projected.sum() - 1.0).abs() < 1e-12);
+        assert!(project_site(&sites[2], &[2, 2]).unwrap().is_none());
+        assert_eq!(
+            project_site(&sites[1], &[2]).unwrap_err().to_string(),
+            "projection target has 1 populations, expected 2"
+        );
+        let invalid = SiteCounts {
+            derived: vec![3, 0],
+            called: vec![2, 2],
+        };
+        assert!(matches!(
+            project_site(&invalid, &[2, 2]),
+            Err(BsfsError::InvalidParameter(_))
+        ));
+    }
+
+    #[rstest]
+    fn test_project_sites(sites: Vec<SiteCounts>) {
+        let projected = project_sites(&sites, &[2, 2]).unwrap();
+
+        assert!((projected.sum() - 2.0).abs() < 1e-12);
+        assert!((projected[IxDyn(&[1, 0])] - 0.5).abs() < 1e-12);
+    }
+
+    #[rstest]
+    fn test_project_matrix() {
+        let mut matrix: ArrayD<u64> = ArrayD::zeros(IxDyn(&[5, 3]));
+        matrix[IxDyn(&[1, 0])] = 2;
+        matrix[IxDyn(&[4, 2])] = 1;
+        let projected = project_matrix(&matrix, &[2, 2]).unwrap();
+
+        assert!((projected[IxDyn(&[1, 0])] - 1.0).abs() < 1e-12);
+        assert!((projected[IxDyn(&[0, 0])] - 1.0).abs() < 1e-12);
+        assert!((projected[IxDyn(&[2, 2])] - 1.0).abs() < 1e-12);
+        assert!(matches!(
+            project_matrix(&matrix, &[2, 2, 2]),
+            Err(BsfsError::Mismatch(_))
+        ));
+        assert_eq!(
+            project_matrix(&matrix, &[2, 3]).unwrap_err().to_string(),
+            "projection target 3 exceeds sample size 2 of population 1"
+        );
+    }
+
+    #[rstest]
+    fn test_scan_projections(sites: Vec<SiteCounts>) {
+        let scan = scan_projections(&sites, 0).unwrap();
+
+        assert_eq!(scan.len(), 4);
+        assert_eq!(scan[0], (1, 0.0));
+        assert!((scan[1].1 - (0.5 + 2.0 / 3.0)).abs() < 1e-12);
+        assert!((scan[2].1 - 1.75).abs() < 1e-12);
+        assert_eq!(best_projection(&sites, 0).unwrap(), Some(3));
+        assert!(matches!(
+            scan_projections(&sites, 2),
+            Err(BsfsError::Mismatch(_))
+        ));
+    }
+}
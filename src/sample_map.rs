@@ -0,0 +1,205 @@
+use crate::error::BsfsError;
+use itertools::Itertools;
+use std::collections::HashMap;
+
+/// Population index of haplotypes not assigned to any population
+pub const UNMAPPED: u16 = u16::MAX;
+
+/// Assignment of haplotypes (columns of a flattened site) to populations
+///
+/// Built once from a haplotype index -> population name map. Populations are in sorted order,
+/// which is also the axis order of the joint SFS.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SampleMap {
+    populations: Vec<String>,
+    hap_to_pop: Vec<u16>,
+    haps_per_pop: Vec<usize>,
+    ploidies: Option<Vec<usize>>,
+}
+
+impl SampleMap {
+    /// Build from haplotype index -> population name
+    ///
+    /// Indices below the largest one that have no population are recorded as unmapped.
+    pub fn new(sample_map: &HashMap<usize, String>) -> Self {
+        let n_haplotypes = sample_map.keys().max().map_or(0, |idx| idx + 1);
+        let haplotypes: Vec<Option<&str>> = (0..n_haplotypes)
+            .map(|idx| sample_map.get(&idx).map(String::as_str))
+            .collect();
+
+        SampleMap::from_haplotypes(&haplotypes)
+    }
+
+    /// Build from the population of each haplotype in site order; None marks unmapped haplotypes
+    pub fn from_haplotypes(haplotypes: &[Option<&str>]) -> Self {
+        let populations: Vec<String> = haplotypes
+            .iter()
+            .flatten()
+            .sorted()
+            .dedup()
+            .map(|population| population.to_string())
+            .collect();
+        assert!(
+            populations.len() < UNMAPPED as usize,
+            "at most {} populations are supported",
+            UNMAPPED
+        );
+
+        let mut haps_per_pop = vec![0; populations.len()];
+        let hap_to_pop: Vec<u16> = haplotypes
+            .iter()
+            .map(|population| match population {
+                Some(population) => {
+                    let pop_idx = populations
+                        .binary_search_by(|other| other.as_str().cmp(population))
+                        .expect("population is listed");
+                    haps_per_pop[pop_idx] += 1;
+                    pop_idx as u16
+                }
+                None => UNMAPPED,
+            })
+            .collect();
+
+        SampleMap {
+            populations,
+            hap_to_pop,
+            haps_per_pop,
+            ploidies: None,
+        }
+    }
+
+    /// Record the ploidy of each sample, in column order, so that sites are checked sample by
+    /// sample rather than by their total number of calls; fails unless they sum to the number of
+    /// haplotypes
+    pub fn with_ploidies(mut self, ploidies: Vec<usize>) -> Result<Self, BsfsError> {
+        let total: usize = ploidies.iter().sum();
+        if total != self.n_haplotypes() {
+            return Err(BsfsError::Mismatch(format!(
+                "sample ploidies sum to {}, sample map has {} haplotypes",
+                total,
+                self.n_haplotypes()
+            )));
+        }
+        self.ploidies = Some(ploidies);
+
+        Ok(self)
+    }
+
+    /// Population names in sorted order
+    pub fn populations(&self) -> &[String] {
+        &self.populations
+    }
+
+    pub fn n_populations(&self) -> usize {
+        self.populations.len()
+    }
+
+    /// Number of haplotype indices covered, including unmapped ones
+    pub fn n_haplotypes(&self) -> usize {
+        self.hap_to_pop.len()
+    }
+
+    /// Population index per haplotype; `UNMAPPED` for haplotypes without a population
+    pub fn hap_to_pop(&self) -> &[u16] {
+        &self.hap_to_pop
+    }
+
+    /// Population index of `haplotype`, if it is mapped
+    pub fn population_index(&self, haplotype: usize) -> Option<usize> {
+        match self.hap_to_pop.get(haplotype) {
+            Some(pop_idx) if *pop_idx != UNMAPPED => Some(*pop_idx as usize),
+            _ => None,
+        }
+    }
+
+    /// Ploidy of each sample, if recorded with `with_ploidies`
+    pub fn ploidies(&self) -> Option<&[usize]> {
+        self.ploidies.as_deref()
+    }
+
+    /// Number of haplotypes per population, in population order
+    pub fn haps_per_pop(&self) -> &[usize] {
+        &self.haps_per_pop
+    }
+
+    /// Shape of joint SFS array; one axis of length n_haps + 1 per population
+    pub fn sfs_shape(&self) -> Vec<usize> {
+        self.haps_per_pop.iter().map(|n_haps| n_haps + 1).collect()
+    }
+
+    /// Haplotype indices without a population
+    pub fn unmapped(&self) -> Vec<usize> {
+        self.hap_to_pop
+            .iter()
+            .positions(|pop_idx| *pop_idx == UNMAPPED)
+            .collect()
+    }
+
+    /// Check that haplotype indices are contiguous from zero, i.e. none is unmapped
+    pub fn validate(&self) -> Result<(), BsfsError> {
+        if !self.hap_to_pop.contains(&UNMAPPED) {
+            return Ok(());
+        }
+
+        Err(BsfsError::UnmappedHaplotypes(self.unmapped()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    fn test_sample_map() {
+        let sample_map = SampleMap::new(&HashMap::from([
+            (0, "popB".to_string()),
+            (1, "popB".to_string()),
+            (2, "popA".to_string()),
+            (4, "popB".to_string()),
+        ]));
+
+        assert_eq!(sample_map.populations(), &["popA", "popB"]);
+        assert_eq!(sample_map.hap_to_pop(), &[1, 1, 0, UNMAPPED, 1]);
+        assert_eq!(sample_map.haps_per_pop(), &[1, 3]);
+        assert_eq!(sample_map.sfs_shape(), vec![2, 4]);
+        assert_eq!(sample_map.population_index(2), Some(0));
+        assert_eq!(sample_map.population_index(3), None);
+        assert_eq!(sample_map.population_index(9), None);
+    }
+
+    #[rstest]
+    fn test_validate() {
+        let contiguous = SampleMap::new(&HashMap::from([(0, "popA".to_string())]));
+        let gapped = SampleMap::new(&HashMap::from([
+            (0, "popA".to_string()),
+            (3, "popA".to_string()),
+        ]));
+
+        assert!(contiguous.validate().is_ok());
+        assert_eq!(
+            SampleMap::from_haplotypes(&[Some("popA"), None]).unmapped(),
+            vec![1]
+        );
+        assert_eq!(gapped.unmapped(), vec![1, 2]);
+        assert!(gapped.validate().unwrap_err().to_string().contains("1, 2"));
+    }
+
+    #[rstest]
+    fn test_with_ploidies() {
+        let sample_map = SampleMap::from_haplotypes(&[Some("popA"); 3]);
+
+        assert_eq!(
+            sample_map
+                .clone()
+                .with_ploidies(vec![2, 1])
+                .unwrap()
+                .ploidies(),
+            Some(&[2, 1][..])
+        );
+        assert!(matches!(
+            sample_map.with_ploidies(vec![2, 2]),
+            Err(BsfsError::Mismatch(_))
+        ));
+    }
+}
@@ -0,0 +1,332 @@
+use crate::{blocks::BlockLength, error::BsfsError, tally::BlockTally};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+const FORMAT: &str = "tally";
+
+/// Run parameters stored alongside a sparse bSFS tally
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TallyMetadata {
+    /// Populations in sorted order, as used by `site_to_entry`
+    pub populations: Vec<String>,
+    /// Haplotypes per population
+    pub sample_sizes: Vec<usize>,
+    #[serde(flatten)]
+    pub block_length: BlockLength,
+    /// Sites were restricted to a mask, so bp blocks count callable bases
+    pub masked: bool,
+    pub kmax: Vec<u64>,
+    pub folded: bool,
+    /// Mutation types are the four types hetA/hetB/hetAB/fixed rather than SFS entries
+    pub four_type: bool,
+    /// Label of each mutation type, e.g. `1_0` or `hetA`
+    pub mutation_types: Vec<String>,
+}
+
+/// One observed block configuration and the number of blocks sharing it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct SparseRow {
+    configuration: Vec<u64>,
+    blocks: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SparseJson {
+    #[serde(flatten)]
+    metadata: TallyMetadata,
+    configurations: Vec<SparseRow>,
+}
+
+/// bSFS tally with metadata, serialised as one row per observed configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SparseTally {
+    pub metadata: TallyMetadata,
+    pub tally: BlockTally,
+}
+
+impl SparseTally {
+    /// Write TSV: `#key=value` header lines, a column line of mutation types, then one row per configuration
+    pub fn write_tsv<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let metadata = &self.metadata;
+        writeln!(out, "#populations={}", metadata.populations.join(","))?;
+        writeln!(
+            out,
+            "#sample_sizes={}",
+            metadata.sample_sizes.iter().join(",")
+        )?;
+        writeln!(out, "#block_length={}", metadata.block_length.length())?;
+        writeln!(out, "#block_unit={}", metadata.block_length.unit())?;
+        writeln!(out, "#masked={}", metadata.masked)?;
+        writeln!(out, "#kmax={}", metadata.kmax.iter().join(","))?;
+        writeln!(out, "#folded={}", metadata.folded)?;
+        writeln!(out, "#four_type={}", metadata.four_type)?;
+        writeln!(out, "{}\tcount", metadata.mutation_types.join("\t"))?;
+
+        for row in self.rows() {
+            writeln!(
+                out,
+                "{}\t{}",
+                row.configuration.iter().join("\t"),
+                row.blocks
+            )?;
+        }
+
+        out.flush()
+    }
+
+    /// Parse TSV written by `write_tsv`
+    pub fn read_tsv<R: BufRead>(reader: R) -> Result<Self, BsfsError> {
+        let mut header: Vec<(String, String)> = vec![];
+        let mut mutation_types: Option<Vec<String>> = None;
+        let mut rows: Vec<SparseRow> = vec![];
+
+        for (idx, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line_number = idx + 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            if let Some(entry) = line.strip_prefix('#') {
+                let (key, value) = entry.split_once('=').ok_or_else(|| {
+                    BsfsError::parse(FORMAT, line_number, "expected '#key=value'")
+                })?;
+                header.push((key.to_string(), value.to_string()));
+            } else if mutation_types.is_none() {
+                let mut columns: Vec<String> = line.split('\t').map(String::from).collect();
+                columns.pop();
+                mutation_types = Some(columns);
+            } else {
+                let mut values: Vec<u64> = line
+                    .split('\t')
+                    .map(|value| value.parse())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| {
+                        BsfsError::parse(FORMAT, line_number, "expected integer counts")
+                    })?;
+                let blocks = values
+                    .pop()
+                    .ok_or_else(|| BsfsError::parse(FORMAT, line_number, "empty row"))?;
+                rows.push(SparseRow {
+                    configuration: values,
+                    blocks,
+                });
+            }
+        }
+
+        let value = |key: &str| -> Result<&str, BsfsError> {
+            header
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, value)| value.as_str())
+                .ok_or_else(|| BsfsError::parse(FORMAT, 0, format!("missing header '#{}='", key)))
+        };
+        let list = |key: &str| -> Result<Vec<String>, BsfsError> {
+            Ok(value(key)?
+                .split(',')
+                .filter(|item| !item.is_empty())
+                .map(String::from)
+                .collect())
+        };
+        let parse = |key: &str| -> Result<Vec<u64>, BsfsError> {
+            list(key)?
+                .iter()
+                .map(|item| item.parse())
+                .collect::<Result<_, _>>()
+                .map_err(|_| BsfsError::parse(FORMAT, 0, format!("invalid '#{}=' header", key)))
+        };
+
+        let metadata = TallyMetadata {
+            populations: list("populations")?,
+            sample_sizes: parse("sample_sizes")?
+                .into_iter()
+                .map(|n| n as usize)
+                .collect(),
+            block_length: value("block_length")?
+                .parse()
+                .ok()
+                .and_then(|length| BlockLength::from_unit(value("block_unit").ok()?, length))
+                .ok_or_else(|| {
+                    BsfsError::parse(
+                        FORMAT,
+                        0,
+                        "invalid '#block_length=' or '#block_unit=' header",
+                    )
+                })?,
+            masked: value("masked")? == "true",
+            kmax: parse("kmax")?,
+            folded: value("folded")? == "true",
+            four_type: value("four_type")? == "true",
+            mutation_types: mutation_types.unwrap_or_default(),
+        };
+
+        SparseTally::from_rows(metadata, rows)
+    }
+
+    /// Write JSON object holding the metadata fields and a `configurations` array
+    pub fn write_json<W: Write>(&self, out: W) -> io::Result<()> {
+        let json = SparseJson {
+            metadata: self.metadata.clone(),
+            configurations: self.rows(),
+        };
+
+        serde_json::to_writer_pretty(out, &json).map_err(io::Error::from)
+    }
+
+    /// Parse JSON written by `write_json`
+    pub fn read_json<R: BufRead>(reader: R) -> Result<Self, BsfsError> {
+        let json: SparseJson = serde_json::from_reader(reader)
+            .map_err(|e| BsfsError::parse("tally JSON", e.line(), e.to_string()))?;
+
+        SparseTally::from_rows(json.metadata, json.configurations)
+    }
+
+    /// Write TSV, or JSON if `path` ends in `.json`
+    pub fn to_path<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let out = BufWriter::new(File::create(&path)?);
+        if is_json(path.as_ref()) {
+            self.write_json(out)
+        } else {
+            self.write_tsv(out)
+        }
+    }
+
+    /// Read TSV, or JSON if `path` ends in `.json`
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, BsfsError> {
+        let reader = BufReader::new(File::open(&path)?);
+        if is_json(path.as_ref()) {
+            SparseTally::read_json(reader)
+        } else {
+            SparseTally::read_tsv(reader)
+        }
+    }
+
+    /// Observed configurations in sorted order
+    fn rows(&self) -> Vec<SparseRow> {
+        self.tally
+            .counts()
+            .iter()
+            .sorted()
+            .map(|(configuration, blocks)| SparseRow {
+                configuration: configuration.clone(),
+                blocks: *blocks,
+            })
+            .collect()
+    }
+
+    fn from_rows(metadata: TallyMetadata, rows: Vec<SparseRow>) -> Result<Self, BsfsError> {
+        let mut tally = BlockTally::new(metadata.kmax.clone());
+        for row in rows {
+            if row.configuration.len() != metadata.kmax.len() {
+                return Err(BsfsError::parse(
+                    FORMAT,
+                    0,
+                    format!(
+                        "configuration has {} mutation types, kmax has {}",
+                        row.configuration.len(),
+                        metadata.kmax.len()
+                    ),
+                ));
+            }
+            tally.add_count(&row.configuration, row.blocks)?;
+        }
+
+        Ok(SparseTally { metadata, tally })
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "json")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::{fixture, rstest};
+    use std::io::Cursor;
+
+    #[fixture]
+    fn sparse() -> SparseTally {
+        let mut tally = BlockTally::new(vec![2, 2, 2, 2]);
+        tally.add_count(&[1, 0, 0, 0], 5).unwrap();
+        tally.add_count(&[0, 0, 0, 0], 12).unwrap();
+        tally.add(&[0, 3, 1, 0]).unwrap();
+
+        SparseTally {
+            metadata: TallyMetadata {
+                populations: vec!["popA".to_string(), "popB".to_string()],
+                sample_sizes: vec![2, 2],
+                block_length: BlockLength::Bases(64),
+                masked: true,
+                kmax: vec![2, 2, 2, 2],
+                folded: false,
+                four_type: true,
+                mutation_types: ["hetA", "hetB", "hetAB", "fixed"]
+                    .map(String::from)
+                    .to_vec(),
+            },
+            tally,
+        }
+    }
+
+    #[rstest]
+    fn test_write_tsv(sparse: SparseTally) {
+        let mut out: Vec<u8> = vec![];
+        sparse.write_tsv(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+
+        assert_eq!(lines[0], "#populations=popA,popB");
+        assert_eq!(lines[3], "#block_unit=bp");
+        assert_eq!(lines[4], "#masked=true");
+        assert_eq!(lines[7], "#four_type=true");
+        assert_eq!(lines[8], "hetA\thetB\thetAB\tfixed\tcount");
+        assert_eq!(
+            &lines[9..],
+            ["0\t0\t0\t0\t12", "0\t3\t1\t0\t1", "1\t0\t0\t0\t5"]
+        );
+    }
+
+    #[rstest]
+    fn test_tsv_round_trip(sparse: SparseTally) {
+        let mut out: Vec<u8> = vec![];
+        sparse.write_tsv(&mut out).unwrap();
+
+        assert_eq!(SparseTally::read_tsv(Cursor::new(out)).unwrap(), sparse)
+    }
+
+    #[rstest]
+    fn test_json_round_trip(sparse: SparseTally) {
+        let mut out: Vec<u8> = vec![];
+        sparse.write_json(&mut out).unwrap();
+
+        assert!(String::from_utf8_lossy(&out).contains("\"block_unit\": \"bp\""));
+        assert_eq!(SparseTally::read_json(Cursor::new(out)).unwrap(), sparse)
+    }
+
+    #[rstest]
+    fn test_read_tsv_malformed() {
+        let text =
+            "#populations=popA\n#sample_sizes=2\n#block_length=1\n#block_unit=bp\n#masked=false\n\
+                    #kmax=2\n#folded=false\n#four_type=false\n1_0\tcount\nx\t1\n";
+        let err = SparseTally::read_tsv(Cursor::new(text)).unwrap_err();
+
+        assert!(err.to_string().contains("line 10"));
+    }
+
+    #[rstest]
+    fn test_read_tsv_block_unit() {
+        let text =
+            "#populations=popA\n#sample_sizes=2\n#block_length=1\n#block_unit=cM\n#masked=false\n\
+                    #kmax=2\n#folded=false\n#four_type=false\n1_0\tcount\n1\t1\n";
+        let err = SparseTally::read_tsv(Cursor::new(text)).unwrap_err();
+
+        assert!(err.to_string().contains("'#block_unit=' header"));
+    }
+}
@@ -0,0 +1,389 @@
+//! Diversity and divergence statistics from the joint SFS
+//!
+//! Raw statistics are sums over sites; `summary_stats` divides them by the callable length to give
+//! per-base values. Tajima's D and Hudson's Fst are ratios and are left as they are.
+
+use crate::error::BsfsError;
+use itertools::Itertools;
+use ndarray::{ArrayD, Axis};
+use std::{
+    io::{self, Write},
+    ops::Add,
+};
+
+/// Entry type of a joint SFS: integer counts, or expected counts of a projected SFS
+pub trait Count: Copy + Default + PartialOrd + Add<Output = Self> {
+    fn as_f64(self) -> f64;
+}
+
+impl Count for u64 {
+    fn as_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl Count for f64 {
+    fn as_f64(self) -> f64 {
+        self
+    }
+}
+
+/// Per-population statistics, per callable base where noted
+#[derive(Debug, Clone, PartialEq)]
+pub struct PopulationStats {
+    pub population: String,
+    /// Segregating sites within the population; expected number for a projected SFS
+    pub segregating: f64,
+    /// Nucleotide diversity per base
+    pub pi: f64,
+    /// Watterson's theta per base
+    pub theta_w: f64,
+    /// None if undefined, e.g. without segregating sites or with fewer than three haplotypes
+    pub tajimas_d: Option<f64>,
+    /// Fay & Wu's H per base; only for polarized data
+    pub fay_wu_h: Option<f64>,
+}
+
+/// Statistics for a pair of populations
+#[derive(Debug, Clone, PartialEq)]
+pub struct PairStats {
+    pub populations: (String, String),
+    /// Absolute divergence per base
+    pub dxy: f64,
+    /// Hudson's Fst; None if dxy is zero
+    pub fst: Option<f64>,
+}
+
+/// Statistics for all populations and pairs of a joint SFS
+#[derive(Debug, Clone, PartialEq)]
+pub struct SummaryStats {
+    pub callable_length: u64,
+    pub populations: Vec<PopulationStats>,
+    pub pairs: Vec<PairStats>,
+}
+
+/// Marginal SFS of the populations at `axes`, in that order, summing over all other populations
+///
+/// Fails if an axis is out of range or repeated.
+pub fn marginalize<A>(sfs: &ArrayD<A>, axes: &[usize]) -> Result<ArrayD<A>, BsfsError>
+where
+    A: Copy + Default + Add<Output = A>,
+{
+    if let Some(axis) = axes.iter().find(|axis| **axis >= sfs.ndim()) {
+        return Err(BsfsError::InvalidParameter(format!(
+            "axis {} out of range for {} populations",
+            axis,
+            sfs.ndim()
+        )));
+    }
+    if let Some(axis) = axes.iter().duplicates().next() {
+        return Err(BsfsError::InvalidParameter(format!(
+            "axis {} is repeated",
+            axis
+        )));
+    }
+
+    let mut marginal = sfs.clone();
+    for axis in (0..sfs.ndim()).rev().filter(|axis| !axes.contains(axis)) {
+        marginal = marginal.fold_axis(Axis(axis), A::default(), |sum, count| *sum + *count);
+    }
+
+    // Remaining axes are in increasing order; put them in the order of `axes`
+    let order: Vec<usize> = axes
+        .iter()
+        .map(|axis| axes.iter().filter(|other| *other < axis).count())
+        .collect();
+    Ok(marginal.permuted_axes(order))
+}
+
+/// One-population SFS of `axis`, summing over all other populations
+pub fn marginal_sfs<T: Count>(sfs: &ArrayD<T>, axis: usize) -> Result<Vec<T>, BsfsError> {
+    Ok(marginalize(sfs, &[axis])?.iter().copied().collect())
+}
+
+/// Two-population SFS of axes `a` and `b` as (derived in `a`, derived in `b`, count), non-zero only
+///
+/// Fails unless `a` and `b` are distinct axes of `sfs`.
+pub fn pairwise_sfs<T: Count>(
+    sfs: &ArrayD<T>,
+    a: usize,
+    b: usize,
+) -> Result<Vec<(usize, usize, T)>, BsfsError> {
+    Ok(marginalize(sfs, &[a, b])?
+        .indexed_iter()
+        .filter(|(_, count)| **count > T::default())
+        .map(|(entry, count)| (entry[0], entry[1], *count))
+        .collect())
+}
+
+/// Number of segregating sites in a one-population SFS
+pub fn segregating_sites<T: Count>(marginal: &[T]) -> T {
+    let n = marginal.len() - 1;
+    (1..n).fold(T::default(), |sum, i| sum + marginal[i])
+}
+
+/// Sum over sites of pairwise differences per pair of haplotypes (theta_pi)
+pub fn pi<T: Count>(marginal: &[T]) -> f64 {
+    let n = marginal.len() - 1;
+    if n < 2 {
+        return 0.0;
+    }
+
+    (1..n)
+        .map(|i| marginal[i].as_f64() * (2 * i * (n - i)) as f64 / (n * (n - 1)) as f64)
+        .sum()
+}
+
+/// Watterson's theta: segregating sites over the harmonic number a1
+pub fn watterson_theta<T: Count>(marginal: &[T]) -> f64 {
+    let n = marginal.len() - 1;
+    if n < 2 {
+        return 0.0;
+    }
+
+    segregating_sites(marginal).as_f64() / harmonic(n - 1, 1)
+}
+
+/// Tajima's D (Tajima 1989); None if the variance term is zero
+pub fn tajimas_d<T: Count>(marginal: &[T]) -> Option<f64> {
+    let n = marginal.len() - 1;
+    let s = segregating_sites(marginal).as_f64();
+    if n < 2 || s == 0.0 {
+        return None;
+    }
+
+    let nf = n as f64;
+    let (a1, a2) = (harmonic(n - 1, 1), harmonic(n - 1, 2));
+    let b1 = (nf + 1.0) / (3.0 * (nf - 1.0));
+    let b2 = 2.0 * (nf * nf + nf + 3.0) / (9.0 * nf * (nf - 1.0));
+    let c1 = b1 - 1.0 / a1;
+    let c2 = b2 - (nf + 2.0) / (a1 * nf) + a2 / (a1 * a1);
+    let (e1, e2) = (c1 / a1, c2 / (a1 * a1 + a2));
+
+    let variance = e1 * s + e2 * s * (s - 1.0);
+    (variance > 0.0).then(|| (pi(marginal) - s / a1) / variance.sqrt())
+}
+
+/// Fay & Wu's H (unnormalised): theta_pi - theta_H; requires a polarized (unfolded) SFS
+pub fn fay_wu_h<T: Count>(marginal: &[T]) -> f64 {
+    let n = marginal.len() - 1;
+    if n < 2 {
+        return 0.0;
+    }
+    let theta_h: f64 = (1..n)
+        .map(|i| marginal[i].as_f64() * (2 * i * i) as f64 / (n * (n - 1)) as f64)
+        .sum();
+
+    pi(marginal) - theta_h
+}
+
+/// Sum over sites of the probability that haplotypes drawn from `a` and `b` differ
+pub fn dxy<T: Count>(pairwise: &[(usize, usize, T)], n_a: usize, n_b: usize) -> f64 {
+    if n_a == 0 || n_b == 0 {
+        return 0.0;
+    }
+
+    pairwise
+        .iter()
+        .map(|(i, j, count)| {
+            count.as_f64() * (i * (n_b - j) + j * (n_a - i)) as f64 / (n_a * n_b) as f64
+        })
+        .sum()
+}
+
+/// Hudson's Fst as a ratio of averages (Bhatia et al. 2013): 1 - mean within / between
+pub fn hudson_fst(pi_a: f64, pi_b: f64, dxy: f64) -> Option<f64> {
+    (dxy > 0.0).then(|| 1.0 - (pi_a + pi_b) / 2.0 / dxy)
+}
+
+/// All statistics of a joint SFS (one axis per population in `populations`), per callable base
+///
+/// Fay & Wu's H is only computed if `polarized`. Fails if `callable_length` is zero.
+pub fn summary_stats<T: Count>(
+    sfs: &ArrayD<T>,
+    populations: &[String],
+    callable_length: u64,
+    polarized: bool,
+) -> Result<SummaryStats, BsfsError> {
+    if callable_length == 0 {
+        return Err(BsfsError::InvalidParameter(
+            "callable length must be at least 1".to_string(),
+        ));
+    }
+    let length = callable_length as f64;
+    let marginals: Vec<Vec<T>> = (0..sfs.ndim())
+        .map(|axis| marginal_sfs(sfs, axis))
+        .collect::<Result<_, _>>()?;
+
+    let population_stats: Vec<PopulationStats> = populations
+        .iter()
+        .zip(&marginals)
+        .map(|(population, marginal)| PopulationStats {
+            population: population.to_string(),
+            segregating: segregating_sites(marginal).as_f64(),
+            pi: pi(marginal) / length,
+            theta_w: watterson_theta(marginal) / length,
+            tajimas_d: tajimas_d(marginal),
+            fay_wu_h: polarized.then(|| fay_wu_h(marginal) / length),
+        })
+        .collect();
+
+    let mut pairs: Vec<PairStats> = vec![];
+    for a in 0..populations.len() {
+        for b in a + 1..populations.len() {
+            let (n_a, n_b) = (sfs.shape()[a] - 1, sfs.shape()[b] - 1);
+            let dxy = dxy(&pairwise_sfs(sfs, a, b)?, n_a, n_b) / length;
+            pairs.push(PairStats {
+                populations: (populations[a].clone(), populations[b].clone()),
+                dxy,
+                fst: hudson_fst(population_stats[a].pi, population_stats[b].pi, dxy),
+            });
+        }
+    }
+
+    Ok(SummaryStats {
+        callable_length,
+        populations: population_stats,
+        pairs,
+    })
+}
+
+impl SummaryStats {
+    /// Write `statistic<TAB>populations<TAB>value` rows; pairs are joined with `,`, undefined values are `NA`
+    pub fn write_tsv<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let na = |value: Option<f64>| value.map_or("NA".to_string(), |value| value.to_string());
+
+        writeln!(out, "statistic\tpopulations\tvalue")?;
+        writeln!(out, "callable_length\t.\t{}", self.callable_length)?;
+        for stats in &self.populations {
+            let population = &stats.population;
+            writeln!(out, "segregating\t{}\t{}", population, stats.segregating)?;
+            writeln!(out, "pi\t{}\t{}", population, stats.pi)?;
+            writeln!(out, "theta_w\t{}\t{}", population, stats.theta_w)?;
+            writeln!(out, "tajimas_d\t{}\t{}", population, na(stats.tajimas_d))?;
+            if let Some(fay_wu_h) = stats.fay_wu_h {
+                writeln!(out, "fay_wu_h\t{}\t{}", population, fay_wu_h)?;
+            }
+        }
+        for pair in &self.pairs {
+            let (a, b) = &pair.populations;
+            writeln!(out, "dxy\t{},{}\t{}", a, b, pair.dxy)?;
+            writeln!(out, "fst\t{},{}\t{}", a, b, na(pair.fst))?;
+        }
+
+        out.flush()
+    }
+}
+
+/// Sum of 1 / i^power for i in 1..=n
+fn harmonic(n: usize, power: i32) -> f64 {
+    (1..=n).map(|i| 1.0 / (i as f64).powi(power)).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::IxDyn;
+    use rstest::{fixture, rstest};
+
+    /// Joint SFS of two populations with two haplotypes each
+    #[fixture]
+    fn sfs() -> ArrayD<u64> {
+        let mut sfs: ArrayD<u64> = ArrayD::zeros(IxDyn(&[3, 3]));
+        sfs[IxDyn(&[0, 0])] = 90;
+        sfs[IxDyn(&[1, 0])] = 4;
+        sfs[IxDyn(&[0, 2])] = 2;
+        sfs[IxDyn(&[1, 1])] = 2;
+        sfs[IxDyn(&[2, 0])] = 2;
+
+        sfs
+    }
+
+    #[rstest]
+    fn test_marginal_sfs(sfs: ArrayD<u64>) {
+        assert_eq!(marginal_sfs(&sfs, 0).unwrap(), vec![92, 6, 2]);
+        assert_eq!(marginal_sfs(&sfs, 1).unwrap(), vec![96, 2, 2]);
+        assert_eq!(pairwise_sfs(&sfs, 1, 0).unwrap()[0], (0, 0, 90));
+        assert_eq!(pairwise_sfs(&sfs, 1, 0).unwrap()[4], (2, 0, 2));
+    }
+
+    #[rstest]
+    fn test_marginalize() {
+        let sfs: ArrayD<u64> =
+            ArrayD::from_shape_vec(IxDyn(&[2, 3, 2]), (0..12).collect()).unwrap();
+        let marginal = marginalize(&sfs, &[2, 0]).unwrap();
+
+        assert_eq!(marginal.shape(), &[2, 2]);
+        assert_eq!(marginal, ndarray::array![[6, 24], [9, 27]].into_dyn());
+        assert_eq!(marginalize(&sfs, &[0, 1, 2]).unwrap(), sfs);
+    }
+
+    #[rstest]
+    #[case(&[0, 3])]
+    #[case(&[1, 1])]
+    fn test_marginalize_invalid_axes(#[case] axes: &[usize]) {
+        let sfs: ArrayD<u64> = ArrayD::zeros(IxDyn(&[2, 3, 2]));
+
+        assert!(matches!(
+            marginalize(&sfs, axes),
+            Err(BsfsError::InvalidParameter(_))
+        ));
+    }
+
+    #[rstest]
+    fn test_neutral_spectrum() {
+        // Expected SFS under neutrality, xi_i proportional to 1 / i: theta estimators agree
+        let marginal: Vec<u64> = vec![0, 6, 3, 2, 0];
+
+        assert!((pi(&marginal) - 6.0).abs() < 1e-12);
+        assert!((watterson_theta(&marginal) - 6.0).abs() < 1e-12);
+        assert!(tajimas_d(&marginal).unwrap().abs() < 1e-12);
+        assert!(fay_wu_h(&marginal).abs() < 1e-12);
+    }
+
+    #[rstest]
+    fn test_skewed_spectrum() {
+        let rare: Vec<u64> = vec![0, 10, 0, 0, 0];
+        let high_frequency: Vec<u64> = vec![0, 0, 0, 10, 0];
+
+        assert!(tajimas_d(&rare).unwrap() < 0.0);
+        assert!(fay_wu_h(&high_frequency) < 0.0);
+        assert_eq!(tajimas_d(&[5u64, 0, 0, 0, 5]), None);
+        assert_eq!(tajimas_d(&[5u64, 3, 5]), None);
+    }
+
+    #[rstest]
+    fn test_dxy(sfs: ArrayD<u64>) {
+        // [1,0]: 1/2; [0,2]: 1; [1,1]: 1/2; [2,0]: 1
+        assert!((dxy(&pairwise_sfs(&sfs, 0, 1).unwrap(), 2, 2) - 7.0).abs() < 1e-12);
+        assert_eq!(hudson_fst(1.0, 1.0, 0.0), None);
+        assert!((hudson_fst(1.0, 3.0, 4.0).unwrap() - 0.5).abs() < 1e-12);
+    }
+
+    #[rstest]
+    fn test_summary_stats(sfs: ArrayD<u64>) {
+        let populations = vec!["popA".to_string(), "popB".to_string()];
+        let stats = summary_stats(&sfs, &populations, 100, false).unwrap();
+        let expected = summary_stats(&sfs.mapv(|count| count as f64), &populations, 100, false);
+
+        assert_eq!(expected.unwrap(), stats);
+        assert_eq!(stats.populations[0].segregating, 6.0);
+        assert!((stats.populations[0].pi - 0.06).abs() < 1e-12);
+        assert!((stats.populations[1].pi - 0.02).abs() < 1e-12);
+        assert_eq!(stats.populations[0].fay_wu_h, None);
+        assert!((stats.pairs[0].dxy - 0.07).abs() < 1e-12);
+        assert!((stats.pairs[0].fst.unwrap() - (1.0 - 0.04 / 0.07)).abs() < 1e-12);
+
+        let mut out: Vec<u8> = vec![];
+        stats.write_tsv(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("pi\tpopB\t0.02\n"));
+        assert!(text.contains("tajimas_d\tpopA\tNA\n"));
+        assert!(text.contains("dxy\tpopA,popB\t0.07\n"));
+        assert!(!text.contains("fay_wu_h"));
+        assert!(matches!(
+            summary_stats(&sfs, &populations, 0, false),
+            Err(BsfsError::InvalidParameter(_))
+        ));
+    }
+}
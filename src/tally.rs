@@ -0,0 +1,189 @@
+use crate::error::BsfsError;
+use ndarray::{ArrayD, Dimension, IxDyn};
+use std::collections::HashMap;
+
+/// Blockwise SFS: number of blocks per (kmax-truncated) mutation configuration
+///
+/// Counts of mutation type `i` above `kmax[i]` are lumped into a single `kmax[i] + 1` bin.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockTally {
+    kmax: Vec<u64>,
+    counts: HashMap<Vec<u64>, u64>,
+}
+
+impl BlockTally {
+    /// Create empty tally with one `kmax` per mutation type
+    pub fn new(kmax: Vec<u64>) -> Self {
+        BlockTally {
+            kmax,
+            counts: HashMap::new(),
+        }
+    }
+
+    /// Create empty tally using the same `kmax` for each of `n_types` mutation types
+    pub fn with_uniform_kmax(n_types: usize, kmax: u64) -> Self {
+        BlockTally::new(vec![kmax; n_types])
+    }
+
+    /// Per-type truncation thresholds
+    pub fn kmax(&self) -> &[u64] {
+        &self.kmax
+    }
+
+    /// Truncate configuration at `kmax`; counts above it become `kmax + 1`
+    ///
+    /// Fails unless the configuration has one count per mutation type.
+    pub fn truncate(&self, configuration: &[u64]) -> Result<Vec<u64>, BsfsError> {
+        if configuration.len() != self.kmax.len() {
+            return Err(BsfsError::Mismatch(format!(
+                "configuration has {} mutation types, kmax has {}",
+                configuration.len(),
+                self.kmax.len()
+            )));
+        }
+
+        Ok(configuration
+            .iter()
+            .zip(&self.kmax)
+            .map(|(count, kmax)| {
+                if count > kmax {
+                    kmax.saturating_add(1)
+                } else {
+                    *count
+                }
+            })
+            .collect())
+    }
+
+    /// Add a single block's configuration
+    pub fn add(&mut self, configuration: &[u64]) -> Result<(), BsfsError> {
+        self.add_count(configuration, 1)
+    }
+
+    /// Add `count` blocks sharing the same configuration
+    pub fn add_count(&mut self, configuration: &[u64], count: u64) -> Result<(), BsfsError> {
+        let truncated = self.truncate(configuration)?;
+        *self.counts.entry(truncated).or_default() += count;
+
+        Ok(())
+    }
+
+    /// Add all blocks of another tally; fails unless both have the same `kmax`
+    pub fn merge(&mut self, other: BlockTally) -> Result<(), BsfsError> {
+        if self.kmax != other.kmax {
+            return Err(BsfsError::Mismatch(format!(
+                "cannot merge tallies with kmax {:?} and {:?}",
+                self.kmax, other.kmax
+            )));
+        }
+
+        for (configuration, count) in other.counts {
+            *self.counts.entry(configuration).or_default() += count;
+        }
+
+        Ok(())
+    }
+
+    /// Number of blocks per truncated configuration
+    pub fn counts(&self) -> &HashMap<Vec<u64>, u64> {
+        &self.counts
+    }
+
+    /// Total number of blocks
+    pub fn n_blocks(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    /// Dense array with one axis of size `kmax[i] + 2` per mutation type, indexed by configuration
+    pub fn to_dense(&self) -> ArrayD<u64> {
+        let shape: Vec<usize> = self.kmax.iter().map(|kmax| *kmax as usize + 2).collect();
+        let mut dense: ArrayD<u64> = ArrayD::zeros(IxDyn(&shape));
+        for (configuration, count) in &self.counts {
+            let entry: Vec<usize> = configuration.iter().map(|k| *k as usize).collect();
+            dense[IxDyn(&entry)] = *count;
+        }
+
+        dense
+    }
+
+    /// Tally from a dense array written by `to_dense`; `kmax` is two less than each axis size
+    pub fn from_dense(dense: &ArrayD<u64>) -> Self {
+        let kmax: Vec<u64> = dense
+            .shape()
+            .iter()
+            .map(|dim| dim.saturating_sub(2) as u64)
+            .collect();
+        let mut tally = BlockTally::new(kmax);
+        // Axis indices never exceed kmax + 1, so entries need no truncation
+        for (entry, count) in dense.indexed_iter().filter(|(_, count)| **count > 0) {
+            let configuration: Vec<u64> = entry.slice().iter().map(|k| *k as u64).collect();
+            tally.counts.insert(configuration, *count);
+        }
+
+        tally
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::{fixture, rstest};
+
+    #[fixture]
+    fn tally() -> BlockTally {
+        let mut tally = BlockTally::new(vec![2, 1]);
+        tally.add(&[0, 0]).unwrap();
+        tally.add(&[3, 1]).unwrap();
+        tally.add(&[5, 1]).unwrap();
+        tally.add(&[1, 4]).unwrap();
+
+        tally
+    }
+
+    #[rstest]
+    fn test_with_uniform_kmax() {
+        assert_eq!(BlockTally::with_uniform_kmax(3, 2).kmax(), &[2, 2, 2]);
+    }
+
+    #[rstest]
+    fn test_truncate(tally: BlockTally) {
+        assert_eq!(tally.truncate(&[2, 1]).unwrap(), vec![2, 1]);
+        assert_eq!(tally.truncate(&[7, 2]).unwrap(), vec![3, 2]);
+        assert_eq!(
+            tally.truncate(&[1, 1, 1]).unwrap_err().to_string(),
+            "configuration has 3 mutation types, kmax has 2"
+        );
+    }
+
+    #[rstest]
+    fn test_counts(tally: BlockTally) {
+        let expected = HashMap::from([(vec![0, 0], 1), (vec![3, 1], 2), (vec![1, 2], 1)]);
+
+        assert_eq!(tally.counts(), &expected);
+        assert_eq!(tally.n_blocks(), 4);
+    }
+
+    #[rstest]
+    fn test_merge(mut tally: BlockTally) {
+        let mut other = BlockTally::new(vec![2, 1]);
+        other.add_count(&[0, 0], 3).unwrap();
+        tally.merge(other).unwrap();
+
+        assert_eq!(tally.counts()[&vec![0, 0]], 4);
+        assert_eq!(tally.n_blocks(), 7);
+        assert!(matches!(
+            tally.merge(BlockTally::new(vec![2, 2])),
+            Err(BsfsError::Mismatch(_))
+        ));
+    }
+
+    #[rstest]
+    fn test_dense(tally: BlockTally) {
+        let dense = tally.to_dense();
+
+        assert_eq!(dense.shape(), &[4, 3]);
+        assert_eq!(dense[IxDyn(&[3, 1])], 2);
+        assert_eq!(dense.sum(), 4);
+        assert_eq!(BlockTally::from_dense(&dense), tally);
+    }
+}
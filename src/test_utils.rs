@@ -0,0 +1,16 @@
+//! Fixtures shared by unit tests
+
+use crate::sample_map::SampleMap;
+use rstest::fixture;
+use std::collections::HashMap;
+
+/// Two populations of two haplotypes each
+#[fixture]
+pub fn sample_map() -> SampleMap {
+    SampleMap::new(&HashMap::from([
+        (0, "popA".to_string()),
+        (1, "popA".to_string()),
+        (2, "popB".to_string()),
+        (3, "popB".to_string()),
+    ]))
+}
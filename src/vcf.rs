@@ -0,0 +1,487 @@
+use crate::{error::BsfsError, Site};
+use flate2::read::MultiGzDecoder;
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+    str::FromStr,
+};
+
+const FORMAT: &str = "VCF";
+
+/// Allele value used for missing calls (`.`)
+pub const MISSING: u32 = u32::MAX;
+
+/// Single VCF record; `calls` holds one allele vector per sample, as taken by `flatten_site`
+#[derive(Debug, Clone, PartialEq)]
+pub struct VcfRecord {
+    pub chrom: String,
+    pub pos: u64,
+    pub ref_allele: String,
+    pub alt_alleles: Vec<String>,
+    /// QUAL; None if `.`
+    pub qual: Option<f64>,
+    /// FILTER entries; empty if `.`
+    pub filter: Vec<String>,
+    /// Raw INFO column
+    pub info: String,
+    /// FORMAT values used by genotype filters, in the same order as `calls`
+    pub sample_format: Vec<SampleFormat>,
+    pub calls: Vec<Vec<u32>>,
+}
+
+/// Per-sample FORMAT values read by `filter::GenotypeFilter`; each None if absent or `.`, AD also
+/// if any of its depths is `.`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SampleFormat {
+    /// FORMAT/GQ
+    pub gq: Option<f64>,
+    /// FORMAT/DP
+    pub dp: Option<u64>,
+    /// FORMAT/AD, one read depth per allele with REF first
+    pub ad: Option<Vec<u64>>,
+}
+
+impl VcfRecord {
+    /// Value of INFO `key`; empty for flags, None if absent
+    pub fn info_field(&self, key: &str) -> Option<&str> {
+        self.info
+            .split(';')
+            .find_map(|entry| match entry.split_once('=') {
+                Some((k, value)) if k == key => Some(value),
+                None if entry == key => Some(""),
+                _ => None,
+            })
+    }
+
+    /// Whether FILTER is `PASS`
+    pub fn is_pass(&self) -> bool {
+        self.filter == ["PASS"]
+    }
+
+    /// Write calls that are entirely missing with the ploidy of their sample, e.g. a haploid `.`
+    /// of a diploid sample as `./.`
+    pub fn expand_missing(&mut self, ploidies: &[usize]) {
+        for (call, ploidy) in self.calls.iter_mut().zip(ploidies) {
+            if call.len() != *ploidy && call.iter().all(|allele| *allele == MISSING) {
+                *call = vec![MISSING; *ploidy];
+            }
+        }
+    }
+
+    /// Keep only the samples at `kept` (column indices), in that order
+    pub fn select_samples(&mut self, kept: &[usize]) {
+        self.calls = kept.iter().map(|idx| self.calls[*idx].clone()).collect();
+        self.sample_format = kept
+            .iter()
+            .map(|idx| self.sample_format[*idx].clone())
+            .collect();
+    }
+}
+
+impl From<VcfRecord> for Site {
+    fn from(record: VcfRecord) -> Self {
+        Site {
+            chrom: record.chrom,
+            pos: record.pos,
+            calls: record.calls,
+        }
+    }
+}
+
+/// Streaming reader over the records of a plain or BGZF-compressed VCF
+pub struct VcfReader<R: BufRead> {
+    reader: R,
+    samples: Vec<String>,
+    line_number: usize,
+    line: String,
+}
+
+impl VcfReader<Box<dyn BufRead>> {
+    /// Open VCF at `path`; gzip/BGZF compression is detected from the magic bytes
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, BsfsError> {
+        let mut file = BufReader::new(File::open(path)?);
+        let is_gzipped = file.fill_buf()?.starts_with(&[0x1f, 0x8b]);
+
+        let reader: Box<dyn BufRead> = if is_gzipped {
+            Box::new(BufReader::new(MultiGzDecoder::new(file)))
+        } else {
+            Box::new(file)
+        };
+
+        VcfReader::new(reader)
+    }
+}
+
+impl<R: BufRead> VcfReader<R> {
+    /// Read header from `reader`, leaving it positioned at the first record
+    pub fn new(reader: R) -> Result<Self, BsfsError> {
+        let mut vcf = VcfReader {
+            reader,
+            samples: vec![],
+            line_number: 0,
+            line: String::new(),
+        };
+
+        loop {
+            if !vcf.next_line()? {
+                return Err(BsfsError::parse(
+                    FORMAT,
+                    vcf.line_number,
+                    "missing #CHROM header line",
+                ));
+            }
+            if vcf.line.starts_with("##") {
+                continue;
+            }
+            if let Some(header) = vcf.line.strip_prefix("#CHROM") {
+                vcf.samples = header.split('\t').skip(9).map(String::from).collect();
+                return Ok(vcf);
+            }
+            return Err(BsfsError::parse(
+                FORMAT,
+                vcf.line_number,
+                "missing #CHROM header line",
+            ));
+        }
+    }
+
+    /// Sample names in column order
+    pub fn samples(&self) -> &[String] {
+        &self.samples
+    }
+
+    /// Read next line into buffer, without line terminator; false at end of input
+    fn next_line(&mut self) -> io::Result<bool> {
+        self.line.clear();
+        if self.reader.read_line(&mut self.line)? == 0 {
+            return Ok(false);
+        }
+        self.line_number += 1;
+        let trimmed_len = self.line.trim_end_matches(['\n', '\r']).len();
+        self.line.truncate(trimmed_len);
+
+        Ok(true)
+    }
+
+    /// Parse current line as a record
+    fn parse_record(&self) -> Result<VcfRecord, BsfsError> {
+        let fields: Vec<&str> = self.line.split('\t').collect();
+        if fields.len() != 9 + self.samples.len() {
+            return Err(BsfsError::parse(
+                FORMAT,
+                self.line_number,
+                format!(
+                    "expected {} columns, found {}",
+                    9 + self.samples.len(),
+                    fields.len()
+                ),
+            ));
+        }
+
+        // POS is 1-based; 0 marks a telomere and has no base to place in a block or window
+        let pos: u64 = fields[1]
+            .parse()
+            .ok()
+            .filter(|pos| *pos > 0)
+            .ok_or_else(|| {
+                BsfsError::parse(
+                    FORMAT,
+                    self.line_number,
+                    format!("invalid POS '{}'", fields[1]),
+                )
+            })?;
+        let alt_alleles: Vec<String> = match fields[4] {
+            "." => vec![],
+            alts => alts.split(',').map(String::from).collect(),
+        };
+        let qual: Option<f64> = match fields[5] {
+            "." => None,
+            qual => Some(qual.parse().map_err(|_| {
+                BsfsError::parse(FORMAT, self.line_number, format!("invalid QUAL '{}'", qual))
+            })?),
+        };
+        let filter: Vec<String> = match fields[6] {
+            "." => vec![],
+            filter => filter.split(';').map(String::from).collect(),
+        };
+        let format: Vec<&str> = fields[8].split(':').collect();
+        let key_idx = |key: &str| format.iter().position(|k| *k == key);
+        let gt_idx = key_idx("GT")
+            .ok_or_else(|| BsfsError::parse(FORMAT, self.line_number, "no GT field in FORMAT"))?;
+        let (gq_idx, dp_idx, ad_idx) = (key_idx("GQ"), key_idx("DP"), key_idx("AD"));
+
+        let (calls, sample_format): (Vec<Vec<u32>>, Vec<SampleFormat>) = fields[9..]
+            .iter()
+            .zip(&self.samples)
+            .map(|(sample, name)| {
+                let mut gt = ".";
+                let mut values = SampleFormat::default();
+                for (idx, value) in sample.split(':').enumerate() {
+                    let idx = Some(idx);
+                    if idx == Some(gt_idx) {
+                        gt = value;
+                    } else if value == "." {
+                        continue;
+                    } else if idx == gq_idx {
+                        values.gq = Some(self.parse_format("GQ", value)?);
+                    } else if idx == dp_idx {
+                        values.dp = Some(self.parse_format("DP", value)?);
+                    } else if idx == ad_idx && !value.split(',').any(|depth| depth == ".") {
+                        values.ad = Some(
+                            value
+                                .split(',')
+                                .map(|depth| self.parse_format("AD", depth))
+                                .collect::<Result<_, _>>()?,
+                        );
+                    }
+                }
+
+                let alleles = parse_gt(gt)
+                    .filter(|alleles| {
+                        alleles.iter().all(|allele| {
+                            *allele == MISSING || *allele as usize <= alt_alleles.len()
+                        })
+                    })
+                    .ok_or_else(|| BsfsError::MalformedGenotype {
+                        line: self.line_number,
+                        chrom: fields[0].to_string(),
+                        pos,
+                        sample: name.to_string(),
+                        value: gt.to_string(),
+                    })?;
+
+                Ok((alleles, values))
+            })
+            .collect::<Result<Vec<_>, BsfsError>>()?
+            .into_iter()
+            .unzip();
+
+        Ok(VcfRecord {
+            chrom: fields[0].to_string(),
+            pos,
+            ref_allele: fields[3].to_string(),
+            alt_alleles,
+            qual,
+            filter,
+            info: fields[7].to_string(),
+            sample_format,
+            calls,
+        })
+    }
+
+    /// Parse one FORMAT value of a sample
+    fn parse_format<T: FromStr>(&self, key: &str, value: &str) -> Result<T, BsfsError> {
+        value.parse().map_err(|_| {
+            BsfsError::parse(
+                FORMAT,
+                self.line_number,
+                format!("invalid FORMAT/{} '{}'", key, value),
+            )
+        })
+    }
+}
+
+impl<R: BufRead> Iterator for VcfReader<R> {
+    type Item = Result<VcfRecord, BsfsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.next_line() {
+                Ok(false) => return None,
+                Ok(true) if self.line.is_empty() => continue,
+                Ok(true) => return Some(self.parse_record()),
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+    }
+}
+
+/// Parse GT value, e.g. `0|1`, `1/1`, `./.`, `0`; None if malformed
+pub fn parse_gt(gt: &str) -> Option<Vec<u32>> {
+    gt.split(['/', '|'])
+        .map(|allele| match allele {
+            "." => Some(MISSING),
+            allele => allele.parse().ok(),
+        })
+        .collect()
+}
+
+/// Read all calls from VCF at `path` into the nested site structure taken by `bsfs_indices`
+pub fn read_calls<P: AsRef<Path>>(path: P) -> Result<Vec<Vec<Vec<u32>>>, BsfsError> {
+    VcfReader::from_path(path)?
+        .map(|record| record.map(|record| record.calls))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use rstest::{fixture, rstest};
+    use std::io::{Cursor, Write};
+
+    #[fixture]
+    fn vcf_text() -> String {
+        [
+            "##fileformat=VCFv4.2",
+            "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tind1\tind2\tind3",
+            "chr1\t10\t.\tA\tT\t50\tPASS\t.\tGT:DP\t0|1:10\t1/1:8\t./.:0",
+            "chr1\t25\t.\tC\tG,A\t50\tPASS\tAA=C;DB\tGT\t0/2\t1\t.|0",
+        ]
+        .join("\n")
+    }
+
+    #[rstest]
+    fn test_parse_gt() {
+        assert_eq!(parse_gt("0|1"), Some(vec![0, 1]));
+        assert_eq!(parse_gt("1/1"), Some(vec![1, 1]));
+        assert_eq!(parse_gt("./."), Some(vec![MISSING, MISSING]));
+        assert_eq!(parse_gt("2"), Some(vec![2]));
+        assert_eq!(parse_gt("0/1/1"), Some(vec![0, 1, 1]));
+        assert_eq!(parse_gt("0/x"), None);
+    }
+
+    #[rstest]
+    fn test_vcf_reader(vcf_text: String) {
+        let reader = VcfReader::new(Cursor::new(vcf_text)).unwrap();
+        assert_eq!(reader.samples(), ["ind1", "ind2", "ind3"]);
+
+        let records: Vec<VcfRecord> = reader.map(|r| r.unwrap()).collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].pos, 10);
+        assert_eq!(
+            records[0].calls,
+            vec![vec![0, 1], vec![1, 1], vec![MISSING, MISSING]]
+        );
+        assert_eq!(records[0].qual, Some(50.0));
+        assert!(records[0].is_pass());
+        assert_eq!(records[0].sample_format[0].dp, Some(10));
+        assert_eq!(records[0].sample_format[1].gq, None);
+        assert_eq!(records[1].sample_format[0], SampleFormat::default());
+        assert_eq!(records[1].alt_alleles, vec!["G", "A"]);
+        assert_eq!(records[1].info_field("AA"), Some("C"));
+        assert_eq!(records[1].info_field("DB"), Some(""));
+        assert_eq!(records[1].info_field("DP"), None);
+        assert_eq!(
+            records[1].calls,
+            vec![vec![0, 2], vec![1], vec![MISSING, 0]]
+        );
+    }
+
+    #[rstest]
+    #[case("GQ", "x", "invalid FORMAT/GQ 'x'")]
+    #[case("DP", "1.5", "invalid FORMAT/DP '1.5'")]
+    #[case("AD", "3,x", "invalid FORMAT/AD 'x'")]
+    fn test_vcf_reader_malformed_format(
+        vcf_text: String,
+        #[case] key: &str,
+        #[case] value: &str,
+        #[case] msg: &str,
+    ) {
+        let text = vcf_text
+            + &format!(
+                "\nchr2\t7\t.\tA\tT\t50\tPASS\t.\tGT:{}\t0/1:{}\t1/1:.\t0/0",
+                key, value
+            );
+        let err = VcfReader::new(Cursor::new(text))
+            .unwrap()
+            .nth(2)
+            .unwrap()
+            .unwrap_err();
+
+        assert!(err.to_string().contains("line 5"));
+        assert!(err.to_string().contains(msg));
+    }
+
+    #[rstest]
+    fn test_vcf_reader_missing_depth(vcf_text: String) {
+        let text = vcf_text + "\nchr2\t7\t.\tA\tT\t50\tPASS\t.\tGT:AD\t0/1:3,.\t1/1:.\t0/0:2,0";
+        let record = VcfReader::new(Cursor::new(text))
+            .unwrap()
+            .nth(2)
+            .unwrap()
+            .unwrap();
+        let depths: Vec<Option<Vec<u64>>> = record
+            .sample_format
+            .into_iter()
+            .map(|values| values.ad)
+            .collect();
+
+        assert_eq!(depths, vec![None, None, Some(vec![2, 0])]);
+    }
+
+    #[rstest]
+    fn test_expand_missing(vcf_text: String) {
+        let mut record = VcfReader::new(Cursor::new(vcf_text))
+            .unwrap()
+            .nth(1)
+            .unwrap()
+            .unwrap();
+        record.calls[1] = vec![MISSING];
+        record.expand_missing(&[2, 2, 2]);
+
+        assert_eq!(
+            record.calls,
+            vec![vec![0, 2], vec![MISSING, MISSING], vec![MISSING, 0]]
+        );
+    }
+
+    #[rstest]
+    fn test_vcf_reader_malformed(vcf_text: String) {
+        let text = vcf_text + "\nchr1\t30\t.\tA\tT\t50\tPASS\t.\tGT\t0/1\t1/1";
+        let results: Vec<Result<VcfRecord, BsfsError>> =
+            VcfReader::new(Cursor::new(text)).unwrap().collect();
+        let err = results[2].as_ref().unwrap_err();
+
+        assert!(matches!(err, BsfsError::Parse { line: 5, .. }));
+        assert!(err.to_string().contains("line 5"));
+    }
+
+    #[rstest]
+    fn test_vcf_reader_position_zero(vcf_text: String) {
+        let text = vcf_text + "\nchr1\t0\t.\tA\tT\t50\tPASS\t.\tGT\t0/1\t1/1\t0/0";
+        let results: Vec<Result<VcfRecord, BsfsError>> =
+            VcfReader::new(Cursor::new(text)).unwrap().collect();
+
+        assert!(results[2]
+            .as_ref()
+            .unwrap_err()
+            .to_string()
+            .contains("invalid POS '0'"));
+    }
+
+    #[rstest]
+    #[case("0/x")]
+    #[case("0/2")]
+    fn test_vcf_reader_malformed_genotype(vcf_text: String, #[case] gt: &str) {
+        let text = vcf_text + &format!("\nchr2\t7\t.\tA\tT\t50\tPASS\t.\tGT\t0/1\t{}\t1/1", gt);
+        let err = VcfReader::new(Cursor::new(text))
+            .unwrap()
+            .nth(2)
+            .unwrap()
+            .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "VCF line 5 (chr2:7): malformed genotype '{}' for sample ind2",
+                gt
+            )
+        );
+    }
+
+    #[rstest]
+    fn test_read_calls_gzipped(vcf_text: String) {
+        let path = std::env::temp_dir().join("bsfs_rust_test_read_calls.vcf.gz");
+        let mut encoder = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+        encoder.write_all(vcf_text.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+
+        let calls = read_calls(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0][1], vec![1, 1]);
+    }
+}
@@ -0,0 +1,368 @@
+use crate::{
+    blocks::{make_callable_blocks, Block, BlockLength},
+    error::BsfsError,
+    mask::CallableMask,
+    sample_map::SampleMap,
+    sfs_from_sites,
+    tally::BlockTally,
+    Site,
+};
+use itertools::Itertools;
+use ndarray::{ArrayD, Dimension};
+use std::io::{self, Write};
+
+/// Window size and step along each chromosome
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowSpec {
+    /// Windows of `size` bp starting every `step` bp from position 0
+    Bases { size: u64, step: u64 },
+    /// Windows of `size` consecutive sites starting every `step` sites
+    Sites { size: usize, step: usize },
+}
+
+/// Joint SFS of the sites in one window
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowSpectrum {
+    pub chrom: String,
+    pub start: u64,
+    pub end: u64,
+    pub n_sites: usize,
+    pub sfs: ArrayD<u64>,
+}
+
+/// bSFS tally of the blocks in one window
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowTally {
+    pub chrom: String,
+    pub start: u64,
+    pub end: u64,
+    pub tally: BlockTally,
+}
+
+/// Run of sites on one chromosome borrowed from the input; `start`/`end` are 0-based half-open,
+/// as in BED
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Window<'a> {
+    pub chrom: &'a str,
+    pub start: u64,
+    pub end: u64,
+    pub sites: &'a [Site],
+    /// 0-based half-open span from the first to the last site of the chromosome
+    pub chrom_extent: (u64, u64),
+}
+
+/// Group sites into (possibly overlapping) windows; sites must be sorted by position within each
+/// chromosome
+///
+/// In `Bases` mode every window up to the last site of a chromosome is returned, including empty
+/// ones. In `Sites` mode windows run from first to last site, and incomplete windows at the end of
+/// a chromosome are dropped. Fails if the size or step is zero, or if a site is at position 0.
+pub fn make_windows(sites: &[Site], spec: WindowSpec) -> Result<Vec<Window<'_>>, BsfsError> {
+    let valid = match spec {
+        WindowSpec::Bases { size, step } => size > 0 && step > 0,
+        WindowSpec::Sites { size, step } => size > 0 && step > 0,
+    };
+    if !valid {
+        return Err(BsfsError::InvalidParameter(
+            "window size and step must be at least 1".to_string(),
+        ));
+    }
+    if let Some(site) = sites.iter().find(|site| site.pos == 0) {
+        return Err(BsfsError::InvalidParameter(format!(
+            "{}:0: site positions are 1-based",
+            site.chrom
+        )));
+    }
+
+    Ok(sites
+        .chunk_by(|a, b| a.chrom == b.chrom)
+        .flat_map(|chrom_sites| chrom_windows(chrom_sites, spec))
+        .collect())
+}
+
+fn chrom_windows(sites: &[Site], spec: WindowSpec) -> Vec<Window<'_>> {
+    let chrom = &sites[0].chrom;
+    let chrom_extent = (sites[0].pos - 1, sites[sites.len() - 1].pos);
+
+    match spec {
+        WindowSpec::Bases { size, step } => {
+            let last = sites[sites.len() - 1].pos - 1;
+            (0..=last)
+                .step_by(step as usize)
+                .map(|start| {
+                    let end = start + size;
+                    let first = sites.partition_point(|site| site.pos - 1 < start);
+                    let after = sites.partition_point(|site| site.pos - 1 < end);
+                    Window {
+                        chrom,
+                        start,
+                        end,
+                        sites: &sites[first..after],
+                        chrom_extent,
+                    }
+                })
+                .collect()
+        }
+        WindowSpec::Sites { size, step } => (0..)
+            .step_by(step)
+            .take_while(|first| first + size <= sites.len())
+            .map(|first| {
+                let window = &sites[first..first + size];
+                Window {
+                    chrom,
+                    start: window[0].pos - 1,
+                    end: window[size - 1].pos,
+                    sites: window,
+                    chrom_extent,
+                }
+            })
+            .collect(),
+    }
+}
+
+/// Joint SFS per window
+pub fn window_spectra(
+    windows: &[Window],
+    sample_map: &SampleMap,
+) -> Result<Vec<WindowSpectrum>, BsfsError> {
+    windows
+        .iter()
+        .map(|window| {
+            Ok(WindowSpectrum {
+                chrom: window.chrom.to_string(),
+                start: window.start,
+                end: window.end,
+                n_sites: window.sites.len(),
+                sfs: sfs_from_sites(window.sites, sample_map)?,
+            })
+        })
+        .collect()
+}
+
+/// Blocks of `length` callable bases tiling `window` from its start
+///
+/// Without `mask` every base is callable, but as in `make_blocks` only the blocks from the first
+/// to the last site of the chromosome are tiled. As in `make_callable_blocks`, blocks without
+/// sites are kept, blocks spanning more than `max_span` bp are dropped, and so is a trailing
+/// incomplete block.
+pub fn window_blocks(
+    window: &Window,
+    mask: Option<&CallableMask>,
+    length: u64,
+    max_span: u64,
+) -> Result<Vec<Block>, BsfsError> {
+    BlockLength::Bases(length).validate()?;
+    let callable = match mask {
+        Some(mask) => CallableMask::span(window.chrom, window.start, window.end).intersect(mask),
+        None => {
+            let (first, last) = window.chrom_extent;
+            let start = window.start + first.saturating_sub(window.start) / length * length;
+            let end = window.start + last.saturating_sub(window.start).div_ceil(length) * length;
+            CallableMask::span(window.chrom, start, end.min(window.end))
+        }
+    };
+
+    make_callable_blocks(window.sites.to_vec(), &callable, length, max_span)
+}
+
+/// bSFS tally per window; `tally` is called with each window, e.g. to split it into blocks with
+/// `window_blocks` and count them with `tally_blocks`
+pub fn window_tallies<F>(windows: &[Window], mut tally: F) -> Result<Vec<WindowTally>, BsfsError>
+where
+    F: FnMut(&Window) -> Result<BlockTally, BsfsError>,
+{
+    windows
+        .iter()
+        .map(|window| {
+            Ok(WindowTally {
+                chrom: window.chrom.to_string(),
+                start: window.start,
+                end: window.end,
+                tally: tally(window)?,
+            })
+        })
+        .collect()
+}
+
+/// Write one row per window: chrom, start, end, number of sites, then every SFS entry in
+/// row-major order, labelled by its derived counts joined with `_`
+pub fn write_window_spectra<W: Write>(mut out: W, spectra: &[WindowSpectrum]) -> io::Result<()> {
+    let labels: Vec<String> = spectra.first().map_or(vec![], |spectrum| {
+        spectrum
+            .sfs
+            .indexed_iter()
+            .map(|(entry, _)| entry.slice().iter().join("_"))
+            .collect()
+    });
+    writeln!(out, "chrom\tstart\tend\tsites\t{}", labels.join("\t"))?;
+
+    for spectrum in spectra {
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}",
+            spectrum.chrom,
+            spectrum.start,
+            spectrum.end,
+            spectrum.n_sites,
+            spectrum.sfs.iter().join("\t")
+        )?;
+    }
+
+    out.flush()
+}
+
+/// Write one row per window and observed configuration: chrom, start, end, the count of each
+/// mutation type in `mutation_types`, then the number of blocks
+pub fn write_window_tallies<W: Write>(
+    mut out: W,
+    mutation_types: &[String],
+    tallies: &[WindowTally],
+) -> io::Result<()> {
+    writeln!(
+        out,
+        "chrom\tstart\tend\t{}\tcount",
+        mutation_types.join("\t")
+    )?;
+
+    for window in tallies {
+        for (configuration, count) in window.tally.counts().iter().sorted() {
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{}\t{}",
+                window.chrom,
+                window.start,
+                window.end,
+                configuration.iter().join("\t"),
+                count
+            )?;
+        }
+    }
+
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocks::tally_blocks;
+    use crate::test_utils::sample_map;
+    use rstest::{fixture, rstest};
+
+    fn site(chrom: &str, pos: u64, calls: Vec<Vec<u32>>) -> Site {
+        Site {
+            chrom: chrom.to_string(),
+            pos,
+            calls,
+        }
+    }
+
+    #[fixture]
+    fn sites() -> Vec<Site> {
+        vec![
+            site("chr1", 1, vec![vec![0, 1], vec![0, 0]]),
+            site("chr1", 4, vec![vec![0, 0], vec![0, 1]]),
+            site("chr1", 12, vec![vec![1, 1], vec![0, 1]]),
+            site("chr2", 3, vec![vec![0, 0], vec![1, 1]]),
+        ]
+    }
+
+    #[rstest]
+    fn test_make_windows_position_zero() {
+        let sites = vec![site("chr1", 0, vec![vec![0, 1]])];
+        let err = make_windows(&sites, WindowSpec::Bases { size: 10, step: 10 }).unwrap_err();
+
+        assert_eq!(err.to_string(), "chr1:0: site positions are 1-based");
+    }
+
+    fn spans<'a>(windows: &[Window<'a>]) -> Vec<(&'a str, u64, u64, usize)> {
+        windows
+            .iter()
+            .map(|w| (w.chrom, w.start, w.end, w.sites.len()))
+            .collect()
+    }
+
+    #[rstest]
+    fn test_make_windows_bases(sites: Vec<Site>) {
+        let windows = make_windows(&sites, WindowSpec::Bases { size: 10, step: 5 }).unwrap();
+        let expected = vec![
+            ("chr1", 0, 10, 2),
+            ("chr1", 5, 15, 1),
+            ("chr1", 10, 20, 1),
+            ("chr2", 0, 10, 1),
+        ];
+
+        assert_eq!(spans(&windows), expected)
+    }
+
+    #[rstest]
+    fn test_make_windows_sites(sites: Vec<Site>) {
+        let windows = make_windows(&sites, WindowSpec::Sites { size: 2, step: 1 }).unwrap();
+        let expected = vec![("chr1", 0, 4, 2), ("chr1", 3, 12, 2)];
+
+        assert_eq!(spans(&windows), expected)
+    }
+
+    #[rstest]
+    #[case(WindowSpec::Bases { size: 10, step: 0 })]
+    #[case(WindowSpec::Sites { size: 0, step: 1 })]
+    fn test_make_windows_invalid(sites: Vec<Site>, #[case] spec: WindowSpec) {
+        let err = make_windows(&sites, spec).unwrap_err();
+
+        assert_eq!(err.to_string(), "window size and step must be at least 1");
+    }
+
+    #[rstest]
+    fn test_window_spectra(sites: Vec<Site>, sample_map: SampleMap) {
+        let windows = make_windows(&sites, WindowSpec::Bases { size: 10, step: 10 }).unwrap();
+        let spectra = window_spectra(&windows, &sample_map).unwrap();
+        let mut out: Vec<u8> = vec![];
+        write_window_spectra(&mut out, &spectra).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "chrom\tstart\tend\tsites\t0_0\t0_1\t0_2\t1_0\t1_1\t1_2\t2_0\t2_1\t2_2\n\
+             chr1\t0\t10\t2\t0\t1\t0\t1\t0\t0\t0\t0\t0\n\
+             chr1\t10\t20\t1\t0\t0\t0\t0\t0\t0\t0\t1\t0\n\
+             chr2\t0\t10\t1\t0\t0\t1\t0\t0\t0\t0\t0\t0\n"
+        );
+    }
+
+    #[rstest]
+    fn test_window_tallies(sites: Vec<Site>, sample_map: SampleMap) {
+        let windows = make_windows(&sites, WindowSpec::Bases { size: 20, step: 20 }).unwrap();
+        let tallies = window_tallies(&windows, |window| {
+            tally_blocks(window_blocks(window, None, 5, 5)?, &sample_map, vec![1; 7])
+        })
+        .unwrap();
+        let mut out: Vec<u8> = vec![];
+        let mutation_types: Vec<String> = (1..=7).map(|idx| format!("m{}", idx)).collect();
+        write_window_tallies(&mut out, &mutation_types, &tallies).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+
+        // Blocks stop at the last site: chr1 has three up to pos 12, chr2 one around pos 3
+        assert_eq!(tallies.len(), 2);
+        assert_eq!(tallies[0].tally.n_blocks(), 3);
+        assert_eq!(tallies[1].tally.n_blocks(), 1);
+        assert_eq!(lines.len(), 5);
+        assert_eq!(
+            lines[0],
+            "chrom\tstart\tend\tm1\tm2\tm3\tm4\tm5\tm6\tm7\tcount"
+        );
+        assert_eq!(lines[4], "chr2\t0\t20\t0\t1\t0\t0\t0\t0\t0\t1");
+    }
+
+    #[rstest]
+    fn test_window_blocks_masked(sites: Vec<Site>) {
+        let mask =
+            CallableMask::from_bed(std::io::Cursor::new("chr1\t2\t8\nchr1\t10\t30\n")).unwrap();
+        let windows = make_windows(&sites, WindowSpec::Bases { size: 20, step: 20 }).unwrap();
+        let blocks = window_blocks(&windows[0], Some(&mask), 5, 10).unwrap();
+        let spans: Vec<(u64, u64, usize)> = blocks
+            .iter()
+            .map(|b| (b.start, b.end, b.sites.len()))
+            .collect();
+
+        assert_eq!(spans, vec![(2, 7, 1), (7, 14, 1), (14, 19, 0)]);
+    }
+}